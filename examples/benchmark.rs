@@ -0,0 +1,77 @@
+use bevy::prelude::*;
+use bevy_voxels::chunks::{self, world_noise::DataGenerator, WorldGenConfig};
+use rayon::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Chunks generated out from the spawn in every direction, override with the first argument
+const DEFAULT_RADIUS: i32 = 6;
+
+/// Generate a cube of chunks around the spawn without a window and report how long it took,
+/// run with `cargo run --release --example benchmark -- <radius>`
+#[allow(clippy::cast_possible_truncation)]
+fn main() {
+    let radius = match std::env::args().nth(1) {
+        None => DEFAULT_RADIUS,
+        Some(arg) => match arg.parse::<u16>() {
+            Ok(radius) => i32::from(radius),
+            Err(_) => {
+                eprintln!("Radius must be a whole number of chunks from 0, got {arg}");
+                std::process::exit(1);
+            }
+        },
+    };
+    let config = WorldGenConfig::default();
+    let data_generator = DataGenerator::new(&config);
+    let center = (config.spawn / chunks::CHUNK_SIZE).round().as_ivec3();
+    let positions: Vec<IVec3> = (-radius..=radius)
+        .flat_map(|x| {
+            (-radius..=radius)
+                .flat_map(move |y| (-radius..=radius).map(move |z| center + IVec3::new(x, y, z)))
+        })
+        .collect();
+
+    let start = Instant::now();
+    let results: Vec<_> = positions
+        .par_iter()
+        .map(|&pos| {
+            let start = Instant::now();
+            let chunk = chunks::generate_chunk(&data_generator, &config, pos);
+            (pos, start.elapsed(), chunk)
+        })
+        .collect();
+    let wall_time = start.elapsed();
+
+    let (mut cubes, mut triangles, mut failed) = (0, 0, 0);
+    let mut total = Duration::ZERO;
+    let mut slowest = (Duration::ZERO, IVec3::ZERO);
+    for (pos, time, chunk) in &results {
+        total += *time;
+        if *time > slowest.0 {
+            slowest = (*time, *pos);
+        }
+        match chunk {
+            Ok(chunk) => {
+                cubes += chunk.n_cubes;
+                triangles += chunk.n_triangles;
+            }
+            Err(error) => {
+                failed += 1;
+                eprintln!("Chunk {pos} failed: {error}");
+            }
+        }
+    }
+    println!(
+        "{} chunks on {} threads in {:.2?}, {} failed",
+        results.len(),
+        rayon::current_num_threads(),
+        wall_time,
+        failed
+    );
+    println!("cubes: {cubes} triangles: {triangles}");
+    println!(
+        "per chunk: mean {:.2?} slowest {:.2?} at {}",
+        total / results.len() as u32,
+        slowest.0,
+        slowest.1
+    );
+}
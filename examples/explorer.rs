@@ -0,0 +1,646 @@
+use bevy::{
+    core_pipeline::experimental::taa::{TemporalAntiAliasBundle, TemporalAntiAliasPlugin},
+    pbr::{
+        wireframe::WireframePlugin, NotShadowCaster, ScreenSpaceAmbientOcclusionBundle,
+        ScreenSpaceAmbientOcclusionQualityLevel, ScreenSpaceAmbientOcclusionSettings,
+    },
+    prelude::*,
+    render::{
+        primitives::Aabb,
+        settings::{WgpuFeatures, WgpuSettings},
+        RenderPlugin,
+    },
+};
+#[cfg(feature = "debug-overlay")]
+use bevy_debug_text_overlay::{screen_print, OverlayPlugin};
+use bevy_voxels::chunks::{
+    self, decoration,
+    density::{self, DensityFn, Sphere},
+    noise_backend::NoiseBackend,
+    occupancy::ChunkOccupancy,
+    palette::{ColorBlindness, ColorLut, Palette},
+    voxel_object::VoxelObject,
+    world_noise::Symmetry,
+};
+#[cfg(feature = "camera-controller")]
+use smooth_bevy_cameras::{
+    controllers::unreal::{UnrealCameraBundle, UnrealCameraController, UnrealCameraPlugin},
+    LookTransform, LookTransformPlugin,
+};
+use std::sync::Arc;
+
+fn main() {
+    let mut app = App::new();
+    app.insert_resource(AmbientLight {
+        brightness: 0.2,
+        ..default()
+    })
+    .add_plugins(DefaultPlugins.set(RenderPlugin {
+        wgpu_settings: WgpuSettings {
+            features: WgpuFeatures::POLYGON_MODE_LINE,
+            // backends: Some(Backends::DX12),
+            ..default()
+        },
+    }))
+    .add_plugins(WireframePlugin)
+    .add_plugins(TemporalAntiAliasPlugin)
+    .add_plugins(chunks::ChunksPlugin)
+    .add_systems(Startup, setup)
+    .add_systems(
+        Update,
+        (
+            cycle_palette,
+            cycle_density,
+            cycle_noise,
+            regenerate_camera_chunk,
+            validate_connectivity,
+            reroll_seed,
+            respawn_on_new_world,
+            spin_voxel_objects,
+            toggle_pause,
+            log_room_changes,
+            toggle_pebbles,
+            cycle_quality,
+            dump_occupancy,
+            place_torch,
+            push_camera_out_of_rock,
+            toggle_heatmap,
+            toggle_density_slice,
+        ),
+    );
+
+    #[cfg(feature = "debug-overlay")]
+    app.add_plugins(OverlayPlugin::default())
+        .add_systems(Update, screen_print_text);
+    #[cfg(feature = "camera-controller")]
+    app.add_plugins((LookTransformPlugin, UnrealCameraPlugin::default()));
+
+    if std::env::args().any(|arg| arg == "--profile") {
+        if !cfg!(feature = "profile") {
+            warn!("--profile without the profile feature won't write a trace");
+        }
+        app.add_systems(OnEnter(chunks::VoxelState::InGame), exit_when_loaded);
+    }
+
+    app.run();
+}
+
+/// Profile one full world generation, quitting once the spawn area has streamed in
+fn exit_when_loaded(stats: Res<chunks::ChunkStats>, mut exit: EventWriter<bevy::app::AppExit>) {
+    info!("World loaded with {} chunks, exiting", stats.chunks);
+    exit.send(bevy::app::AppExit);
+}
+
+#[cfg(feature = "debug-overlay")]
+fn screen_print_text(
+    time: Res<Time>,
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+    chunk_stats: Res<chunks::ChunkStats>,
+    exploration: Res<chunks::exploration::ExplorationState>,
+    voxel_world: chunks::VoxelWorld,
+    cameras: Query<&Transform, With<Camera3d>>,
+) {
+    let current_time = time.elapsed_seconds_f64();
+    let at_interval = |t: f64| current_time % t < time.delta_seconds_f64();
+    if at_interval(0.1) {
+        let last_fps = 1.0 / time.delta_seconds();
+        screen_print!("current time: {current_time:.2}");
+        screen_print!(col: Color::CYAN, "fps: {last_fps:.0}");
+        screen_print!(
+            "chunks: {} cubes: {} triangles: {} stripped: {} 16 bit indices: {}",
+            chunk_stats.chunks,
+            chunk_stats.cubes,
+            chunk_stats.triangles,
+            chunk_stats.stripped_triangles,
+            chunk_stats.compact_chunks
+        );
+        screen_print!(
+            "queued: {} generating: {} mesh assets: {}",
+            chunk_stats.queued,
+            chunk_stats.generating,
+            chunk_stats.mesh_assets
+        );
+        screen_print!(
+            "explored chunks: {} rooms: {}",
+            exploration.visited_chunks.len(),
+            exploration.visited_rooms.len()
+        );
+
+        // Name the room the camera is standing in, corridors stay unnamed
+        if let Ok(transform) = cameras.get_single() {
+            let nearby = Aabb::from_min_max(
+                transform.translation - Vec3::splat(2.0),
+                transform.translation + Vec3::splat(2.0),
+            );
+            let nearby_voxels = voxel_world.iter_solid_in_aabb(nearby).count();
+            screen_print!("voxels within 2m: {nearby_voxels}");
+            if voxel_world.is_solid(transform.translation) {
+                screen_print!(col: Color::ORANGE, "inside rock");
+            }
+            if let Some(chunk) = voxel_world.chunk_at(transform.translation) {
+                screen_print!("chunk triangles: {}", chunk.n_triangles);
+            }
+
+            let acoustics = chunks::acoustics::sample(&data_generator, transform.translation);
+            screen_print!(
+                "room volume: {:.0} ceiling: {:.1} reverb: {:.2} low pass: {:.0}Hz",
+                acoustics.room_volume,
+                acoustics.ceiling_height,
+                acoustics.reverb,
+                acoustics.low_pass
+            );
+
+            let (x, z) = (transform.translation.x, transform.translation.z);
+            let data2d = data_generator.get_data_2d(x, z);
+            if data2d.room_dist < data2d.room_size {
+                let room_name = chunks::rooms::room_name(
+                    data_generator.seed,
+                    chunks::rooms::room_cell(&data_generator, x, z),
+                );
+                screen_print!(col: Color::GOLD, "room: {room_name}");
+            }
+        }
+    }
+}
+
+/// Cycle the chunk color palette with P, accessibility palettes then a warm grade
+fn cycle_palette(
+    keys: Res<Input<KeyCode>>,
+    mut config: ResMut<chunks::WorldGenConfig>,
+    mut index: Local<usize>,
+) {
+    if !keys.just_pressed(KeyCode::P) {
+        return;
+    }
+    *index = (*index + 1) % 5;
+    config.palette = match *index {
+        0 => Palette::Identity,
+        1 => Palette::color_blind(ColorBlindness::Deuteranopia),
+        2 => Palette::color_blind(ColorBlindness::Protanopia),
+        3 => Palette::color_blind(ColorBlindness::Tritanopia),
+        _ => Palette::Lut(Arc::new(ColorLut::from_fn(16, |color| {
+            (color * Vec3::new(1.1, 1.0, 0.85)).powf(0.9)
+        }))),
+    };
+}
+
+/// Cycle between worlds composed from the built in caves with G, including symmetric debug worlds
+fn cycle_density(
+    keys: Res<Input<KeyCode>>,
+    mut config: ResMut<chunks::WorldGenConfig>,
+    mut index: Local<usize>,
+) {
+    if !keys.just_pressed(KeyCode::G) {
+        return;
+    }
+    *index = (*index + 1) % 5;
+    // Symmetric worlds, any difference between the copies is a mesher bug
+    config.symmetry = match *index {
+        2 => Symmetry::MirrorX,
+        3 => Symmetry::Radial { segments: 6 },
+        _ => Symmetry::None,
+    };
+    config.density = match *index {
+        0 | 2 | 3 => None,
+        1 => Some(Arc::new(density::caves().warp(0.02, 8.0))),
+        // Boulders left standing on a grid through every room
+        _ => Some(Arc::new(
+            density::caves().subtract(
+                Sphere {
+                    center: Vec3::ZERO,
+                    radius: 3.0,
+                }
+                .repeat(Vec3::new(12.0, 0.0, 12.0)),
+            ),
+        )),
+    };
+}
+
+/// Scatter pebbles across the floors of lush areas with B, again to clear them
+fn toggle_pebbles(keys: Res<Input<KeyCode>>, mut config: ResMut<chunks::WorldGenConfig>) {
+    if !keys.just_pressed(KeyCode::B) {
+        return;
+    }
+    if !config.decorators.is_empty() {
+        config.decorators.clear();
+        return;
+    }
+    config
+        .decorators
+        .push(Arc::new(|data_generator, chunk_pos, rng| {
+            decoration::scatter_on_floors(data_generator, chunk_pos, rng, 0.6, |data2d| {
+                data2d.lushness > 0.4
+            })
+            .into_iter()
+            .map(|floor| {
+                let center = floor + Vec3::Y * 0.05;
+                chunks::Cube {
+                    pos: center,
+                    size: 0.1,
+                    color: Color::rgb(0.55, 0.5, 0.45),
+                    bounds: Aabb::from_min_max(center - 0.05, center + 0.05),
+                }
+            })
+            .collect()
+        }));
+}
+
+/// Step through the graphics quality presets with Q
+fn cycle_quality(keys: Res<Input<KeyCode>>, mut preset: ResMut<chunks::quality::QualityPreset>) {
+    if keys.just_pressed(KeyCode::Q) {
+        *preset = preset.next();
+        info!("Quality preset {:?}", *preset);
+    }
+}
+
+/// Log the packed occupancy of the camera's chunk with O, checking it survives a round trip
+fn dump_occupancy(
+    keys: Res<Input<KeyCode>>,
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+    cameras: Query<&Transform, With<Camera3d>>,
+) {
+    if !keys.just_pressed(KeyCode::O) {
+        return;
+    }
+    let Ok(transform) = cameras.get_single() else {
+        return;
+    };
+    let pos = transform.translation;
+    let elevation = data_generator.get_data_2d(pos.x, pos.z).elevation;
+    let chunk = ((pos - Vec3::Y * elevation) / chunks::CHUNK_SIZE)
+        .round()
+        .as_ivec3();
+    let occupancy = ChunkOccupancy::sample(&data_generator, chunk);
+    let bytes = occupancy.to_bytes();
+    info!(
+        "Chunk {chunk}: {} solid cells, {} colors, {} bytes",
+        occupancy.solid_count(),
+        occupancy.palette.len(),
+        bytes.len()
+    );
+    if ChunkOccupancy::from_bytes(&bytes).as_ref() != Some(&occupancy) {
+        warn!("Chunk {chunk} occupancy didn't survive a round trip");
+    }
+}
+
+/// Hang a torch where the camera is with T, again in the same spot takes it down
+fn place_torch(
+    keys: Res<Input<KeyCode>>,
+    mut block_entities: ResMut<chunks::block_entities::BlockEntities>,
+    cameras: Query<&Transform, With<Camera3d>>,
+) {
+    if !keys.just_pressed(KeyCode::T) {
+        return;
+    }
+    let Ok(transform) = cameras.get_single() else {
+        return;
+    };
+    let pos = transform.translation;
+    if block_entities.contains(pos) {
+        block_entities.remove(pos);
+        return;
+    }
+    block_entities.insert(
+        pos,
+        Arc::new(|block| {
+            block.insert(PointLight {
+                color: Color::rgb(1.0, 0.7, 0.4),
+                intensity: 400.0,
+                range: 12.0,
+                ..default()
+            });
+        }),
+    );
+}
+
+/// Push the camera back into the nearest air when it flies into rock, C turns it off to clip through
+#[allow(clippy::cast_precision_loss)]
+fn push_camera_out_of_rock(
+    keys: Res<Input<KeyCode>>,
+    mut enabled: Local<Option<bool>>,
+    voxel_world: chunks::VoxelWorld,
+    #[cfg(feature = "camera-controller")] mut cameras: Query<&mut LookTransform, With<Camera3d>>,
+    #[cfg(not(feature = "camera-controller"))] mut cameras: Query<&mut Transform, With<Camera3d>>,
+) {
+    let enabled = enabled.get_or_insert(true);
+    if keys.just_pressed(KeyCode::C) {
+        *enabled = !*enabled;
+    }
+    if !*enabled {
+        return;
+    }
+    for mut camera in &mut cameras {
+        #[cfg(feature = "camera-controller")]
+        let eye = camera.eye;
+        #[cfg(not(feature = "camera-controller"))]
+        let eye = camera.translation;
+        if !voxel_world.is_solid(eye) {
+            continue;
+        }
+        // Search outward shell by shell so the camera moves as little as possible
+        let offset = (1..=8).find_map(|step| {
+            let distance = step as f32 * chunks::SMALLEST_CUBE_SIZE;
+            [
+                Vec3::Y,
+                Vec3::NEG_Y,
+                Vec3::X,
+                Vec3::NEG_X,
+                Vec3::Z,
+                Vec3::NEG_Z,
+            ]
+            .into_iter()
+            .map(|direction| direction * distance)
+            .find(|&offset| !voxel_world.is_solid(eye + offset))
+        });
+        let Some(offset) = offset else {
+            continue;
+        };
+        #[cfg(feature = "camera-controller")]
+        {
+            camera.eye += offset;
+            camera.target += offset;
+        }
+        #[cfg(not(feature = "camera-controller"))]
+        {
+            camera.translation += offset;
+        }
+    }
+}
+
+/// Cycle the noise the world is built from with N
+fn cycle_noise(
+    keys: Res<Input<KeyCode>>,
+    mut config: ResMut<chunks::WorldGenConfig>,
+    mut index: Local<usize>,
+) {
+    if !keys.just_pressed(KeyCode::N) {
+        return;
+    }
+    *index = (*index + 1) % 4;
+    config.noise = match *index {
+        0 => NoiseBackend::OpenSimplex,
+        1 => NoiseBackend::Perlin,
+        2 => NoiseBackend::Value,
+        _ => NoiseBackend::fbm(4),
+    };
+}
+
+/// Rebuild the chunk the camera is in with R, handy when tweaking generation
+fn regenerate_camera_chunk(
+    keys: Res<Input<KeyCode>>,
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+    cameras: Query<&Transform, With<Camera3d>>,
+    mut regenerate: EventWriter<chunks::RegenerateChunk>,
+) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+    if let Ok(transform) = cameras.get_single() {
+        // Chunks are drawn raised by their elevation, find the one generated under the camera
+        let pos = transform.translation;
+        let elevation = data_generator.get_data_2d(pos.x, pos.z).elevation;
+        let chunk = ((pos - Vec3::Y * elevation) / chunks::CHUNK_SIZE)
+            .round()
+            .as_ivec3();
+        regenerate.send(chunks::RegenerateChunk(chunk));
+    }
+}
+
+/// Show how long each chunk took to generate with H, green is quick and red is slow
+fn toggle_heatmap(
+    keys: Res<Input<KeyCode>>,
+    mut heatmap: ResMut<chunks::diagnostics::GenerationHeatmap>,
+) {
+    if keys.just_pressed(KeyCode::H) {
+        heatmap.0 = !heatmap.0;
+    }
+}
+
+/// Drop a cross section of the generator in front of the camera with X, again to remove it
+fn toggle_density_slice(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    cameras: Query<&Transform, With<Camera3d>>,
+    slices: Query<Entity, With<chunks::slice::DensitySlice>>,
+) {
+    if !keys.just_pressed(KeyCode::X) {
+        return;
+    }
+    if let Ok(entity) = slices.get_single() {
+        commands.entity(entity).despawn_recursive();
+        return;
+    }
+    if let Ok(transform) = cameras.get_single() {
+        // Stand it upright across the view, far enough ahead to see all of it
+        let forward = (transform.forward() * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+        let slice = chunks::slice::DensitySlice::default();
+        let center = transform.translation + forward * slice.size * 0.75;
+        commands.spawn((
+            SpatialBundle::from_transform(
+                Transform::from_translation(center).looking_to(forward, Vec3::Y),
+            ),
+            slice,
+        ));
+    }
+}
+
+/// Check the air around the camera for unreachable pockets and shafts with V, again to clear
+fn validate_connectivity(
+    keys: Res<Input<KeyCode>>,
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+    mut report: ResMut<chunks::connectivity::ConnectivityReport>,
+    cameras: Query<&Transform, With<Camera3d>>,
+) {
+    if !keys.just_pressed(KeyCode::V) {
+        return;
+    }
+    if !report.pockets.is_empty() || !report.shafts.is_empty() {
+        *report = default();
+        return;
+    }
+    if let Ok(transform) = cameras.get_single() {
+        *report = chunks::connectivity::validate_region(
+            &data_generator,
+            transform.translation,
+            IVec3::new(32, 12, 32),
+            1.0,
+        );
+        info!(
+            "Connectivity: {} unreachable pockets, {} shafts",
+            report.pockets.len(),
+            report.shafts.len()
+        );
+    }
+}
+
+/// Build a fresh world from a random seed with F5, without restarting
+fn reroll_seed(keys: Res<Input<KeyCode>>, mut config: ResMut<chunks::WorldGenConfig>) {
+    if keys.just_pressed(KeyCode::F5) {
+        config.seed = rand::random();
+        info!("Rerolled world seed to {}", config.seed);
+    }
+}
+
+/// Move the camera back to the safe spawn when the world is rebuilt from a new seed, other
+/// settings keep the same caves so the camera stays where it is
+fn respawn_on_new_world(
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+    mut last_seed: Local<Option<u32>>,
+    #[cfg(feature = "camera-controller")] mut cameras: Query<&mut LookTransform, With<Camera3d>>,
+    #[cfg(not(feature = "camera-controller"))] mut cameras: Query<&mut Transform, With<Camera3d>>,
+) {
+    let seed = data_generator.seed;
+    if last_seed.replace(seed).is_none_or(|last| last == seed) {
+        return;
+    }
+    let eye = data_generator.safe_spawn_position();
+    let target = eye + Vec3::X;
+    for mut camera in &mut cameras {
+        #[cfg(feature = "camera-controller")]
+        {
+            camera.eye = eye;
+            camera.target = target;
+        }
+        #[cfg(not(feature = "camera-controller"))]
+        {
+            *camera = Transform::from_translation(eye).looking_at(target, Vec3::Y);
+        }
+    }
+}
+
+/// Pause and resume chunk streaming with Escape once the world has loaded
+fn toggle_pause(
+    keys: Res<Input<KeyCode>>,
+    state: Res<State<chunks::VoxelState>>,
+    mut next_state: ResMut<NextState<chunks::VoxelState>>,
+) {
+    if !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    match state.get() {
+        chunks::VoxelState::InGame => next_state.set(chunks::VoxelState::Paused),
+        chunks::VoxelState::Paused => next_state.set(chunks::VoxelState::InGame),
+        chunks::VoxelState::Loading => {}
+    }
+}
+
+/// Announce rooms as the camera walks through them
+fn log_room_changes(
+    mut entered: EventReader<chunks::exploration::RoomEntered>,
+    mut exited: EventReader<chunks::exploration::RoomExited>,
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+) {
+    for event in exited.iter() {
+        info!(
+            "{:?} left {}",
+            event.anchor,
+            chunks::rooms::room_name(data_generator.seed, event.room)
+        );
+    }
+    for event in entered.iter() {
+        info!(
+            "{:?} entered {}",
+            event.anchor,
+            chunks::rooms::room_name(data_generator.seed, event.room)
+        );
+    }
+}
+
+/// Slowly turn voxel objects so it's clear they aren't stuck to the chunk grid
+fn spin_voxel_objects(time: Res<Time>, mut objects: Query<&mut Transform, With<VoxelObject>>) {
+    for mut transform in &mut objects {
+        transform.rotate_y(time.delta_seconds() * 0.5);
+    }
+}
+
+/// Set up a simple 3D scene
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    data_generator: Res<chunks::world_noise::DataGenerator>,
+) {
+    // Camera, starting in the air pocket carved at spawn
+    let eye = data_generator.safe_spawn_position();
+    let target = eye + Vec3::X;
+    let mut camera = commands.spawn((
+        Camera3dBundle::default(),
+        chunks::StreamingAnchor,
+        // Falloff follows the render distance
+        FogSettings {
+            color: Color::rgba(0.05, 0.05, 0.05, 1.0),
+            ..default()
+        },
+    ));
+    camera
+        .insert(ScreenSpaceAmbientOcclusionBundle {
+            settings: ScreenSpaceAmbientOcclusionSettings {
+                quality_level: ScreenSpaceAmbientOcclusionQualityLevel::Low,
+            },
+            ..Default::default()
+        })
+        .insert(TemporalAntiAliasBundle::default());
+    #[cfg(feature = "camera-controller")]
+    camera.insert(UnrealCameraBundle::new(
+        UnrealCameraController::default(),
+        eye,
+        target,
+        Vec3::Y,
+    ));
+    #[cfg(not(feature = "camera-controller"))]
+    camera.insert(Transform::from_translation(eye).looking_at(target, Vec3::Y));
+
+    // Boulder made of voxels beside the spawn, free to move unlike the chunks around it
+    commands.spawn((
+        SpatialBundle::from_transform(Transform::from_translation(eye + Vec3::new(4.0, 0.0, 2.0))),
+        VoxelObject::from_fn(UVec3::splat(8), 0.25, |pos| {
+            (pos.length() < 0.9).then_some(Color::rgb(0.45, 0.4, 0.35))
+        }),
+    ));
+
+    // Plane
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(shape::Plane::from_size(5.0).into()),
+        material: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
+        ..default()
+    });
+    // Cube
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+        material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
+        transform: Transform::from_xyz(0.0, 0.5, 0.0),
+        ..default()
+    });
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Cube { size: 0.5 })),
+        material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
+        transform: Transform::from_xyz(0.0, 0.5, 1.0),
+        ..default()
+    });
+    // Sky
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::default())),
+            material: materials.add(StandardMaterial {
+                base_color: Color::hex("888888").unwrap(),
+                unlit: true,
+                cull_mode: None,
+                ..default()
+            }),
+            transform: Transform::from_scale(Vec3::splat(1_000_000.0)),
+            ..default()
+        },
+        NotShadowCaster,
+    ));
+    // Sun
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            color: Color::rgb(0.98, 0.95, 0.82),
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(0.0, 0.0, 0.0)
+            .looking_at(Vec3::new(-0.15, -0.05, 0.25), Vec3::Y),
+        ..default()
+    });
+}
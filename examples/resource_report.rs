@@ -0,0 +1,29 @@
+use bevy::prelude::*;
+use bevy_voxels::chunks::{resources, world_noise::DataGenerator, WorldGenConfig, CHUNK_SIZE};
+
+/// Chunks summed out from the spawn in every direction, override with the second argument
+const DEFAULT_RADIUS: i32 = 16;
+
+/// Print the minerals around the spawn for a seed, to compare collectible balance between seeds,
+/// run with `cargo run --release --example resource_report -- <seed> <radius>`
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut config = WorldGenConfig::default();
+    if let Some(seed) = args.next().and_then(|arg| arg.parse().ok()) {
+        config.seed = seed;
+    }
+    let radius = args
+        .next()
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_RADIUS);
+    let data_generator = DataGenerator::new(&config);
+
+    // Rooms sit around y 0, so only a few chunks up and down hold anything worth counting
+    let center = (config.spawn / CHUNK_SIZE).round().as_ivec3();
+    let extent = IVec3::new(radius, radius.min(4), radius);
+    let total = resources::balance_report(&data_generator, center - extent, center + extent);
+    println!(
+        "seed {}: calcium {:.1} graphite {:.1} iron {:.1}",
+        config.seed, total.calcium, total.graphite, total.iron
+    );
+}
@@ -1,182 +1,375 @@
 // mod raycast;
+pub mod acoustics;
+pub mod block_entities;
+pub mod connectivity;
+pub mod decoration;
+pub mod density;
+pub mod diagnostics;
+pub mod exploration;
+pub mod noise_backend;
+pub mod occupancy;
+pub mod palette;
+pub mod quality;
 mod render;
+pub mod resources;
+pub mod rooms;
+pub mod slice;
+mod streaming;
 mod subdivision;
-mod world_noise;
+pub mod voxel_object;
+pub mod voxel_world;
+pub mod world_noise;
 
-use bevy::prelude::*;
-use rayon::prelude::*;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use subdivision::chunk_render;
+use bevy::{prelude::*, render::primitives::Aabb};
+pub use render::MeshError;
+use std::sync::Arc;
+use std::time::Duration;
+pub use streaming::{
+    teardown_world, ChunkCrashDump, ChunkGenFailed, ChunkRenderLayers, ChunkSpawnAnimation,
+    ChunkStats, RegenerateChunk, StreamingAnchor,
+};
+pub use voxel_world::VoxelWorld;
 
 pub const CHUNK_SIZE: f32 = 2.0;
+/// Default for `WorldGenConfig::smallest_cube_size`, and the fixed grid occupancy exports and
+/// block entities use whatever the config says
 pub const SMALLEST_CUBE_SIZE: f32 = 0.25;
-#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-const RENDER_DISTANCE: usize = (128f32 / CHUNK_SIZE) as usize;
 
-type VisitedSet = Arc<Mutex<HashSet<(i32, i32, i32)>>>;
+/// How far from an anchor chunks load, in world units, fog and the far plane of anchored cameras follow it
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct RenderDistance(pub f32);
 
-pub struct Chunk {
-    pub lods: Vec<Mesh>,
-    pub chunk_pos: Vec3,
-    pub n_cubes: usize,
-    pub n_triangles: usize,
-}
-
-pub struct Cube {
-    pub pos: Vec3,
-    pub size: f32,
-    pub color: Vec3,
+impl Default for RenderDistance {
+    fn default() -> Self {
+        RenderDistance(128.0)
+    }
 }
 
-struct ExploreResult {
-    chunks: Vec<Chunk>,
-    new_queue: Vec<(i32, i32, i32)>,
+/// Settings for how the world is generated and colored, changing it reloads all chunks
+#[derive(Resource, Reflect, Clone)]
+#[reflect(Resource)]
+pub struct WorldGenConfig {
+    pub seed: u32,
+    pub noise: noise_backend::NoiseBackend,
+    #[reflect(ignore)]
+    pub palette: palette::Palette,
+    /// Where the player starts, a floored air pocket is always carved here
+    pub spawn: Vec3,
+    /// Where the world is air, None uses the built in rooms and corridors
+    #[reflect(ignore)]
+    pub density: Option<Arc<dyn density::DensityFn>>,
+    /// Open sky above the caves, None keeps the world underground
+    pub surface: Option<world_noise::SurfaceSettings>,
+    /// Warp applied on top of the density, rooms and corridors lose their regular ellipsoid shapes
+    pub warp: Option<density::DomainWarp>,
+    /// Fold the whole world into mirrored copies, for spotting one sided mesher bugs
+    pub symmetry: world_noise::Symmetry,
+    /// Stretch cubes on chunk borders outward to hide cracks between chunks at different lods
+    pub skirts: bool,
+    pub shading: ChunkShading,
+    /// How many of a cube's corners may be air before it is split, divided by the cube size,
+    /// higher keeps more coarse cubes poking into the air
+    pub air_tolerance: f32,
+    /// Size of the cubes in the finest lod, each lod doubles it
+    pub smallest_cube_size: f32,
+    /// Size of the cubes in the coarsest lod, at most the chunk size
+    pub largest_cube_size: f32,
+    /// Boxes kept as air or rock for structures, set them before the world loads, changing them
+    /// regenerates every chunk
+    pub reservations: Vec<world_noise::Reservation>,
+    /// Scatter and structure hooks, run in order for every chunk
+    #[reflect(ignore)]
+    pub decorators: Vec<decoration::Decorator>,
 }
 
-/// Chunk search algorithm to generate chunks around the player
-#[allow(
-    clippy::cast_precision_loss,
-    clippy::cast_possible_truncation,
-    clippy::cast_possible_wrap,
-    clippy::cast_sign_loss
-)]
-pub fn chunk_search(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    // Start timer
-    let start = std::time::Instant::now();
-    // Create world noise data generator
-    let data_generator = world_noise::DataGenerator::new();
-
-    // Initialize state
-    let mut queue = Vec::new();
-    let visited: VisitedSet = Arc::default();
-
-    queue.push((0, 0, 0));
-
-    let mut chunks: Vec<Chunk> = Vec::new();
-    while !queue.is_empty() {
-        let results: Vec<ExploreResult> = queue
-            .par_iter()
-            .map(|&chunk| explore_chunk(&visited, &data_generator, chunk))
-            .collect();
-        queue.clear();
-        for result in results {
-            chunks.extend(result.chunks);
-            queue.extend(result.new_queue);
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        WorldGenConfig {
+            seed: 4321,
+            noise: default(),
+            palette: default(),
+            spawn: Vec3::ZERO,
+            density: None,
+            surface: None,
+            warp: None,
+            symmetry: world_noise::Symmetry::None,
+            skirts: false,
+            shading: ChunkShading::Lit,
+            air_tolerance: 1.0,
+            smallest_cube_size: SMALLEST_CUBE_SIZE,
+            largest_cube_size: CHUNK_SIZE,
+            reservations: Vec::new(),
+            decorators: Vec::new(),
         }
     }
+}
 
-    // After all chunks have been explored, spawn them
-    let total = chunks.len();
-    let mut cubes = 0;
-    let mut triangles = 0;
-
-    for chunk in chunks {
-        // Get wanted lod based on distance, if close to origin it should be 0, if close to RENDER_DISTANCE it should be n_lods
-        let n_lods = (CHUNK_SIZE / SMALLEST_CUBE_SIZE).log2() + 1.0;
-        let target_lod =
-            (chunk.chunk_pos.length() / RENDER_DISTANCE as f32 * n_lods).floor() as usize;
-        // Render out the target_lod if it exists
-        if let Some(mesh) = chunk.lods.get(target_lod) {
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(mesh.clone()),
-                material: materials.add(StandardMaterial {
-                    base_color: Color::WHITE,
-                    ..default()
-                }),
-                transform: Transform::from_translation(chunk.chunk_pos),
-                ..Default::default()
-            });
+/// Where the world is in its lifecycle, the spawn area streams in during `Loading` and moves on
+/// to `InGame` once it is done, `Paused` stops streaming until the game resumes
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoxelState {
+    #[default]
+    Loading,
+    InGame,
+    Paused,
+}
+
+/// How chunk meshes are lit
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ChunkShading {
+    /// Full PBR lighting with shadows
+    #[default]
+    Lit,
+    /// Unlit, with light from the sky above baked into the vertex colors, for low end devices
+    Flat,
+}
+
+impl ChunkShading {
+    /// Brightness baked into a face pointing along `normal`
+    pub fn face_light(self, normal: Vec3) -> f32 {
+        match self {
+            ChunkShading::Lit => 1.0,
+            // Hemisphere light, bright from above and dim from the ground
+            ChunkShading::Flat => 0.75 + normal.y * 0.25 - normal.z.abs() * 0.05,
         }
-        cubes += chunk.n_cubes;
-        triangles += chunk.n_triangles;
     }
-
-    println!("Total: {total} Cubes: {cubes} Triangles: {triangles}");
-    println!("Time: {:#?}", start.elapsed());
 }
 
-/// Function to handle exploration of each chunk
-#[allow(
-    clippy::cast_possible_truncation,
-    clippy::cast_precision_loss,
-    clippy::cast_possible_wrap,
-    clippy::cast_sign_loss
-)]
-fn explore_chunk(
-    visited: &VisitedSet,
-    data_generator: &world_noise::DataGenerator,
-    (chunk_x, chunk_y, chunk_z): (i32, i32, i32),
-) -> ExploreResult {
-    let directions = [
-        (-1, 0, 0),
-        (1, 0, 0),
-        (0, -1, 0),
-        (0, 1, 0),
-        (0, 0, -1),
-        (0, 0, 1),
-    ];
-
-    let mut chunks = Vec::new();
-    let mut new_queue = Vec::new();
-
-    for &direction in &directions {
-        let neighbor = (
-            chunk_x + direction.0,
-            chunk_y + direction.1,
-            chunk_z + direction.2,
-        );
-        // Get position in visited array
-        let neighbor_normalised = (
-            neighbor.0 + RENDER_DISTANCE as i32,
-            neighbor.1 + RENDER_DISTANCE as i32,
-            neighbor.2 + RENDER_DISTANCE as i32,
-        );
-
-        let is_out_of_bounds = neighbor_normalised.0 < 0
-            || neighbor_normalised.1 < 0
-            || neighbor_normalised.2 < 0
-            || neighbor_normalised.0 > RENDER_DISTANCE as i32 * 2
-            || neighbor_normalised.1 > RENDER_DISTANCE as i32 * 2
-            || neighbor_normalised.2 > RENDER_DISTANCE as i32 * 2;
-        if is_out_of_bounds {
-            continue;
+impl WorldGenConfig {
+    /// Check the settings make sense before building a world from them
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.air_tolerance.is_finite() || self.air_tolerance < 0.0 {
+            return Err(format!(
+                "air tolerance {} must be finite and not negative",
+                self.air_tolerance
+            ));
         }
-        if visited.lock().unwrap().contains(&neighbor_normalised) {
-            continue;
+        // Each lod doubles the cube size, so both steps have to be whole powers of two
+        let is_power_of_two = |ratio: f32| ratio >= 1.0 && ratio.log2().fract() == 0.0;
+        if !self.smallest_cube_size.is_finite() || self.smallest_cube_size <= 0.0 {
+            return Err(format!(
+                "smallest cube size {} must be finite and positive",
+                self.smallest_cube_size
+            ));
         }
-        // Calculate the distance from the origin, only create the chunk if it's within the render distance
-        let distance = ((neighbor.0.pow(2) + neighbor.1.pow(2) + neighbor.2.pow(2)) as f32).sqrt();
-        if distance > RENDER_DISTANCE as f32 {
-            continue;
+        if !is_power_of_two(self.largest_cube_size / self.smallest_cube_size) {
+            return Err(format!(
+                "largest cube size {} must be a power of two times the smallest {}",
+                self.largest_cube_size, self.smallest_cube_size
+            ));
         }
-
-        visited.lock().unwrap().insert(neighbor_normalised);
-
-        let chunk = chunk_render(
-            data_generator,
-            Vec3::new(
-                neighbor.0 as f32 * CHUNK_SIZE,
-                neighbor.2 as f32 * CHUNK_SIZE,
-                neighbor.1 as f32 * CHUNK_SIZE,
-            ),
-            CHUNK_SIZE,
-        );
-
-        let blocking = chunk.n_cubes == 1;
-        // If chunk is empty don't render it
-        if chunk.n_cubes > 0 {
-            chunks.push(chunk);
+        if !is_power_of_two(CHUNK_SIZE / self.largest_cube_size) {
+            return Err(format!(
+                "chunk size {CHUNK_SIZE} must be a power of two times the largest cube size {}",
+                self.largest_cube_size
+            ));
+        }
+        for reservation in &self.reservations {
+            if reservation.min.cmpgt(reservation.max).any() {
+                return Err(format!(
+                    "reservation min {} is above its max {}",
+                    reservation.min, reservation.max
+                ));
+            }
         }
-        // If chunk is blocking, don't explore it further
-        if !blocking {
-            new_queue.push(neighbor);
+        Ok(())
+    }
+}
+
+/// Generate and mesh the chunk at `pos`, in chunk coordinates, the same work a streaming task
+/// does but without the ECS
+pub fn generate_chunk(
+    data_generator: &world_noise::DataGenerator,
+    config: &WorldGenConfig,
+    pos: IVec3,
+) -> Result<Chunk, MeshError> {
+    subdivision::chunk_render(
+        data_generator,
+        config,
+        pos.as_vec3() * CHUNK_SIZE,
+        CHUNK_SIZE,
+    )
+}
+
+pub struct ChunksPlugin;
+
+impl Plugin for ChunksPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<WorldGenConfig>()
+            // Everything a crash dump holds, so it can be loaded back
+            .register_type::<ChunkCrashDump>()
+            .register_type::<noise_backend::NoiseBackend>()
+            .register_type::<Option<world_noise::SurfaceSettings>>()
+            .register_type::<world_noise::SurfaceSettings>()
+            .register_type::<Option<density::DomainWarp>>()
+            .register_type::<density::DomainWarp>()
+            .register_type::<world_noise::Symmetry>()
+            .register_type::<ChunkShading>()
+            .register_type::<Vec<world_noise::Reservation>>()
+            .register_type::<world_noise::Reservation>()
+            .register_type::<RenderDistance>()
+            .register_type::<ChunkSpawnAnimation>()
+            .register_type::<ChunkRenderLayers>()
+            .register_type::<block_entities::LightBudget>()
+            .register_type::<quality::QualityPreset>()
+            .register_type::<slice::DensitySlice>()
+            .register_type::<ChunkStats>()
+            .register_type::<StreamingAnchor>()
+            .register_type::<Chunk>()
+            .register_type::<voxel_world::VoxelSurface>()
+            .init_resource::<WorldGenConfig>()
+            .init_resource::<RenderDistance>()
+            .init_resource::<ChunkSpawnAnimation>()
+            .init_resource::<ChunkRenderLayers>()
+            .init_resource::<quality::QualityPreset>()
+            .add_event::<ChunkGenFailed>()
+            .add_event::<RegenerateChunk>()
+            .add_event::<exploration::RoomEntered>()
+            .add_event::<exploration::RoomExited>()
+            .add_state::<VoxelState>();
+        let config = app.world.resource::<WorldGenConfig>();
+        if let Err(error) = config.validate() {
+            panic!("Invalid world settings: {error}");
         }
+        let data_generator = world_noise::DataGenerator::new(config);
+        app.insert_resource(data_generator)
+            .init_resource::<streaming::ChunkMap>()
+            .init_resource::<ChunkStats>()
+            .init_resource::<block_entities::BlockEntities>()
+            .init_resource::<block_entities::LightBudget>()
+            .init_resource::<streaming::ChunkMeshes>()
+            .init_resource::<connectivity::ConnectivityReport>()
+            .init_resource::<exploration::ExplorationState>()
+            .add_systems(Startup, streaming::setup_chunk_material)
+            .add_systems(
+                Update,
+                (
+                    quality::apply_quality_preset,
+                    streaming::sync_chunk_material,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    streaming::reload_on_config_change,
+                    streaming::update_anchors,
+                    streaming::regenerate_chunks,
+                    streaming::queue_chunks,
+                    streaming::receive_chunks,
+                    streaming::update_lods,
+                    streaming::release_chunk_meshes,
+                )
+                    .chain()
+                    .run_if(not(in_state(VoxelState::Paused))),
+            )
+            .add_systems(
+                Update,
+                streaming::finish_loading
+                    .after(streaming::receive_chunks)
+                    .run_if(in_state(VoxelState::Loading)),
+            )
+            .add_systems(
+                Update,
+                (
+                    streaming::sync_fog_to_render_distance,
+                    streaming::animate_appearing_chunks,
+                    streaming::sync_chunk_render_layers,
+                    streaming::disable_deep_shadows.after(streaming::receive_chunks),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    exploration::track_exploration,
+                    exploration::detect_room_changes,
+                )
+                    .run_if(in_state(VoxelState::InGame)),
+            )
+            .add_systems(Update, connectivity::draw_connectivity)
+            // Bake flat shading into objects the same frame the material stops being lit
+            .add_systems(
+                Update,
+                voxel_object::mesh_voxel_objects.after(streaming::sync_chunk_material),
+            )
+            .add_systems(Update, slice::draw_density_slices)
+            .add_systems(
+                Update,
+                (
+                    block_entities::spawn_block_entities.after(streaming::receive_chunks),
+                    block_entities::limit_block_lights,
+                ),
+            )
+            // Don't leave workers finishing chunks nobody will see
+            .add_systems(
+                Last,
+                teardown_world.run_if(on_event::<bevy::app::AppExit>()),
+            )
+            .add_plugins(diagnostics::ChunkDiagnosticsPlugin);
     }
+}
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Chunk {
+    #[reflect(ignore)]
+    pub lods: Vec<Mesh>,
+    /// Lod of the first mesh in `lods`, far away chunks drop their finer lods
+    pub first_lod: usize,
+    pub chunk_pos: Vec3,
+    pub n_cubes: usize,
+    pub n_triangles: usize,
+    /// The finest lod uses 16 bit indices, coarser lods always fit them
+    pub compact_indices: bool,
+    /// Zero area or duplicated triangles left out of the finest lod
+    pub n_stripped: usize,
+    /// How long the chunk took to generate and mesh on its worker
+    pub generation_time: Duration,
+    /// Cubes of the finest lod, in world space, empty once the chunk drops its fine lods
+    pub cubes: Vec<Cube>,
+}
 
-    ExploreResult { chunks, new_queue }
+impl Chunk {
+    pub fn lod(&self, lod: usize) -> Option<&Mesh> {
+        self.lods.get(lod.checked_sub(self.first_lod)?)
+    }
+}
+
+#[derive(Reflect)]
+pub struct Cube {
+    pub pos: Vec3,
+    pub size: f32,
+    pub color: Color,
+    /// Exact voxel bounds in world space, without the jitter and overlap the mesh is drawn with
+    pub bounds: Aabb,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_checks_cube_sizes() {
+        let config = |smallest_cube_size, largest_cube_size| WorldGenConfig {
+            smallest_cube_size,
+            largest_cube_size,
+            ..default()
+        };
+        assert_eq!(WorldGenConfig::default().validate(), Ok(()));
+        assert_eq!(config(0.5, 1.0).validate(), Ok(()));
+        assert_eq!(config(CHUNK_SIZE, CHUNK_SIZE).validate(), Ok(()));
+        for (smallest, largest) in [
+            (0.0, 2.0),
+            (-0.25, 2.0),
+            (0.3, 2.0),
+            (0.25, 1.5),
+            (0.5, 4.0),
+        ] {
+            assert!(
+                config(smallest, largest).validate().is_err(),
+                "{smallest} to {largest} passed"
+            );
+        }
+    }
 }
@@ -0,0 +1,45 @@
+use crate::chunks::world_noise::DataGenerator;
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+/// Rooms at least this big reverb fully, smaller ones fade towards a dry sound
+const LARGE_ROOM_VOLUME: f32 = 20_000.0;
+
+/// How a position in the caves should sound, for driving reverb and filtering
+pub struct Acoustics {
+    /// Volume of the room the position is in, zero in corridors and rock
+    pub room_volume: f32,
+    /// Height of the room's ceiling above its middle, zero outside rooms
+    pub ceiling_height: f32,
+    /// Wet mix from 0 dry to 1 for the largest caverns
+    pub reverb: f32,
+    /// Low pass cutoff in hertz, tight corridors muffle sound
+    pub low_pass: f32,
+}
+
+/// Estimate the acoustics at world space `pos` from the room it falls in
+pub fn sample(data_generator: &DataGenerator, pos: Vec3) -> Acoustics {
+    let data2d = data_generator.get_data_2d(pos.x, pos.z);
+    if data2d.room_dist >= data2d.room_size {
+        return Acoustics {
+            room_volume: 0.0,
+            ceiling_height: 0.0,
+            reverb: 0.1,
+            low_pass: 4000.0 + data2d.corridor_width * 1000.0,
+        };
+    }
+
+    // Rooms are ellipsoids squashed by their floor and ceiling factors, sum the two halves
+    let radius = data2d.room_size;
+    let ceiling_height = radius / data2d.room_ceiling;
+    let floor_depth = radius / data2d.room_floor;
+    let room_volume = 2.0 / 3.0 * PI * radius * radius * (ceiling_height + floor_depth);
+
+    let size = (room_volume / LARGE_ROOM_VOLUME).clamp(0.0, 1.0);
+    Acoustics {
+        room_volume,
+        ceiling_height,
+        reverb: 0.2 + size * 0.8,
+        low_pass: 8000.0 + size * 12000.0,
+    }
+}
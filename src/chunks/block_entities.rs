@@ -0,0 +1,151 @@
+use crate::chunks::{
+    world_noise::DataGenerator, Chunk, StreamingAnchor, CHUNK_SIZE, SMALLEST_CUBE_SIZE,
+};
+use bevy::{
+    ecs::system::EntityCommands,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::sync::Arc;
+
+type BlockLights = (With<BlockEntity>, With<PointLight>);
+
+/// Adds the components for a block, like a torch's light or a chest's inventory
+pub type BlockSpawner = Arc<dyn Fn(&mut EntityCommands) + Send + Sync>;
+
+/// Entities tied to voxels, spawned as children of the chunk holding them so they stream in and
+/// out with it, state on them is lost when their chunk unloads and chunks without cubes get none
+#[derive(Resource, Default)]
+pub struct BlockEntities {
+    blocks: HashMap<IVec3, BlockSpawner>,
+    /// Cells inserted or removed since block entities were last spawned
+    changed: Vec<IVec3>,
+}
+
+impl BlockEntities {
+    /// Tie an entity to the voxel at world space `pos`, replacing any already there
+    pub fn insert(&mut self, pos: Vec3, spawner: BlockSpawner) {
+        let cell = voxel_cell(pos);
+        self.blocks.insert(cell, spawner);
+        self.changed.push(cell);
+    }
+
+    pub fn contains(&self, pos: Vec3) -> bool {
+        self.blocks.contains_key(&voxel_cell(pos))
+    }
+
+    pub fn remove(&mut self, pos: Vec3) {
+        let cell = voxel_cell(pos);
+        if self.blocks.remove(&cell).is_some() {
+            self.changed.push(cell);
+        }
+    }
+}
+
+/// Most block lights left on at once, the ones nearest an anchor win, keeps clustered lighting
+/// under its limits once torches are everywhere
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct LightBudget(pub usize);
+
+impl Default for LightBudget {
+    fn default() -> Self {
+        LightBudget(32)
+    }
+}
+
+/// Voxel a block entity belongs to, in smallest cube cells
+#[derive(Component)]
+pub struct BlockEntity {
+    pub cell: IVec3,
+}
+
+fn voxel_cell(pos: Vec3) -> IVec3 {
+    (pos / SMALLEST_CUBE_SIZE).floor().as_ivec3()
+}
+
+/// Spawn block entities under newly loaded chunks, and respawn only the cells inserted or removed
+/// since the last run so the other blocks keep their state
+pub fn spawn_block_entities(
+    mut commands: Commands,
+    mut block_entities: ResMut<BlockEntities>,
+    data_generator: Res<DataGenerator>,
+    chunks: Query<(Entity, Ref<Chunk>)>,
+    spawned: Query<(Entity, &BlockEntity)>,
+) {
+    let changed: HashSet<IVec3> =
+        std::mem::take(&mut block_entities.bypass_change_detection().changed)
+            .into_iter()
+            .collect();
+    for (entity, block) in &spawned {
+        if changed.contains(&block.cell) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    // Every loaded chunk can hold a changed cell, otherwise only new chunks need their blocks
+    let targets: HashMap<IVec3, (Entity, bool)> = chunks
+        .iter()
+        .filter(|(_, chunk)| !changed.is_empty() || chunk.is_added())
+        .map(|(entity, chunk)| {
+            let pos = (chunk.chunk_pos / CHUNK_SIZE).round().as_ivec3();
+            (pos, (entity, chunk.is_added()))
+        })
+        .collect();
+    if targets.is_empty() {
+        return;
+    }
+
+    for (&cell, spawner) in &block_entities.blocks {
+        let pos = (cell.as_vec3() + 0.5) * SMALLEST_CUBE_SIZE;
+        // Chunks are drawn raised by their elevation, find the one that generated this voxel
+        let elevation = data_generator.get_data_2d(pos.x, pos.z).elevation;
+        let chunk = ((pos - Vec3::Y * elevation) / CHUNK_SIZE)
+            .round()
+            .as_ivec3();
+        let Some(&(entity, added)) = targets.get(&chunk) else {
+            continue;
+        };
+        if !added && !changed.contains(&cell) {
+            continue;
+        }
+        let local = pos - chunk.as_vec3() * CHUNK_SIZE;
+        commands.entity(entity).with_children(|parent| {
+            let mut block = parent.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(local)),
+                BlockEntity { cell },
+            ));
+            spawner(&mut block);
+        });
+    }
+}
+
+/// Hide block lights past the budget, furthest from every anchor first
+pub fn limit_block_lights(
+    budget: Res<LightBudget>,
+    anchors: Query<&GlobalTransform, With<StreamingAnchor>>,
+    mut lights: Query<(&GlobalTransform, &mut Visibility), BlockLights>,
+) {
+    let anchors: Vec<Vec3> = anchors.iter().map(GlobalTransform::translation).collect();
+    let mut by_distance: Vec<_> = lights
+        .iter_mut()
+        .map(|(transform, visibility)| {
+            let pos = transform.translation();
+            let distance = anchors
+                .iter()
+                .map(|anchor| anchor.distance_squared(pos))
+                .fold(f32::INFINITY, f32::min);
+            (distance, visibility)
+        })
+        .collect();
+    by_distance.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (index, (_, mut visibility)) in by_distance.into_iter().enumerate() {
+        let wanted = if index < budget.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
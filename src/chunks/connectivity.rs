@@ -0,0 +1,144 @@
+use crate::chunks::world_noise::DataGenerator;
+use bevy::{prelude::*, utils::HashMap};
+use std::collections::VecDeque;
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+/// Air pockets and shafts found by the last validation, drawn as a gizmo overlay
+#[derive(Resource, Default)]
+pub struct ConnectivityReport {
+    pub step: f32,
+    /// Pockets the start can't reach, largest first
+    pub pockets: Vec<AirPocket>,
+    /// Columns of air running through the whole region, with their top and bottom
+    pub shafts: Vec<(Vec3, Vec3)>,
+}
+
+pub struct AirPocket {
+    /// Cell centers in world space
+    pub cells: Vec<Vec3>,
+    /// Pockets touching the edge may still connect outside the region
+    pub touches_boundary: bool,
+}
+
+/// Flood fill the air around world space `center` in cells of `step`, reporting what the start can't reach
+#[allow(clippy::cast_precision_loss)]
+pub fn validate_region(
+    data_generator: &DataGenerator,
+    center: Vec3,
+    half_extent: IVec3,
+    step: f32,
+) -> ConnectivityReport {
+    // Undo the elevation offset chunks are rendered with to get back to generator space
+    let center = center - Vec3::Y * data_generator.get_data_2d(center.x, center.z).elevation;
+    let origin = (center / step).round().as_ivec3();
+    let (min, max) = (origin - half_extent, origin + half_extent);
+
+    // Sample air once per cell, keeping each column's elevation to place overlays in world space
+    let mut air = HashMap::new();
+    let mut elevations = HashMap::new();
+    let mut shafts = Vec::new();
+    for x in min.x..=max.x {
+        for z in min.z..=max.z {
+            let (world_x, world_z) = (x as f32 * step, z as f32 * step);
+            let data2d = data_generator.get_data_2d(world_x, world_z);
+            elevations.insert((x, z), data2d.elevation);
+            let mut column_open = true;
+            for y in min.y..=max.y {
+                let is_air = data_generator.get_data_3d(&data2d, world_x, world_z, y as f32 * step);
+                column_open &= is_air;
+                air.insert(IVec3::new(x, y, z), is_air);
+            }
+            if column_open {
+                let to_world =
+                    |y: i32| Vec3::new(world_x, y as f32 * step + data2d.elevation, world_z);
+                shafts.push((to_world(max.y), to_world(min.y)));
+            }
+        }
+    }
+
+    // Label each pocket, the one holding the start is connected by definition
+    let mut pocket_of = HashMap::new();
+    let mut pockets = Vec::new();
+    let mut start_pocket = None;
+    for z in min.z..=max.z {
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let seed = IVec3::new(x, y, z);
+                if !air[&seed] || pocket_of.contains_key(&seed) {
+                    continue;
+                }
+                let id = pockets.len();
+                let mut cells = Vec::new();
+                let mut touches_boundary = false;
+                let mut frontier = VecDeque::from([seed]);
+                pocket_of.insert(seed, id);
+                while let Some(cell) = frontier.pop_front() {
+                    touches_boundary |= cell.cmpeq(min).any() || cell.cmpeq(max).any();
+                    if cell == origin {
+                        start_pocket = Some(id);
+                    }
+                    cells.push(cell);
+                    for offset in NEIGHBOURS {
+                        let next = cell + offset;
+                        if air.get(&next) == Some(&true) && !pocket_of.contains_key(&next) {
+                            pocket_of.insert(next, id);
+                            frontier.push_back(next);
+                        }
+                    }
+                }
+                pockets.push((cells, touches_boundary));
+            }
+        }
+    }
+    // Starting in rock, treat the biggest pocket as the one the player would dig into
+    let start_pocket =
+        start_pocket.or_else(|| (0..pockets.len()).max_by_key(|&id| pockets[id].0.len()));
+
+    let mut pockets: Vec<AirPocket> = pockets
+        .into_iter()
+        .enumerate()
+        .filter(|&(id, _)| Some(id) != start_pocket)
+        .map(|(_, (cells, touches_boundary))| AirPocket {
+            cells: cells
+                .into_iter()
+                .map(|cell| cell.as_vec3() * step + Vec3::Y * elevations[&(cell.x, cell.z)])
+                .collect(),
+            touches_boundary,
+        })
+        .collect();
+    pockets.sort_by_key(|pocket| std::cmp::Reverse(pocket.cells.len()));
+
+    ConnectivityReport {
+        step,
+        pockets,
+        shafts,
+    }
+}
+
+/// Sealed pockets in red, pockets that may open up beyond the region in yellow, shafts in magenta
+pub fn draw_connectivity(report: Res<ConnectivityReport>, mut gizmos: Gizmos) {
+    for pocket in &report.pockets {
+        let color = if pocket.touches_boundary {
+            Color::YELLOW
+        } else {
+            Color::RED
+        };
+        for &cell in &pocket.cells {
+            gizmos.cuboid(
+                Transform::from_translation(cell).with_scale(Vec3::splat(report.step * 0.5)),
+                color,
+            );
+        }
+    }
+    for &(top, bottom) in &report.shafts {
+        gizmos.line(top, bottom, Color::FUCHSIA);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::chunks::{
+    world_noise::{Data2D, DataGenerator},
+    Cube, CHUNK_SIZE,
+};
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use std::{f32::consts::TAU, sync::Arc};
+
+/// Candidates tried around each point before giving up on it, Bridson's usual 30
+const SCATTER_ATTEMPTS: usize = 30;
+
+/// Adds cubes to a chunk, called for every lod with a fresh `ChunkRng` so each lod places the same things
+pub type Decorator = Arc<dyn Fn(&DataGenerator, Vec3, &mut ChunkRng) -> Vec<Cube> + Send + Sync>;
+
+/// Random numbers that only depend on the world seed, the chunk and which hook is asking,
+/// so placement doesn't change with generation order or threads
+pub struct ChunkRng(StdRng);
+
+impl ChunkRng {
+    /// `stream` separates hooks in the same chunk, give each its own
+    #[allow(clippy::cast_sign_loss)]
+    pub fn new(seed: u32, chunk: IVec3, stream: u64) -> Self {
+        let mut hash = u64::from(seed);
+        for value in [
+            u64::from(chunk.x as u32),
+            u64::from(chunk.y as u32),
+            u64::from(chunk.z as u32),
+            stream,
+        ] {
+            hash = splitmix64(hash ^ value);
+        }
+        ChunkRng(StdRng::seed_from_u64(hash))
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl RngCore for ChunkRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest);
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+/// Points on the floors of a chunk at least `spacing` apart, so props spread out evenly instead
+/// of clumping like noise thresholds, `mask` skips columns such as the wrong biome or lushness.
+/// Spacing is only kept within the chunk, points either side of a border can sit closer.
+/// Panics unless `spacing` is finite and above zero
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn scatter_on_floors(
+    data_generator: &DataGenerator,
+    chunk_pos: Vec3,
+    rng: &mut ChunkRng,
+    spacing: f32,
+    mask: impl Fn(&Data2D) -> bool,
+) -> Vec<Vec3> {
+    assert!(
+        spacing.is_finite() && spacing > 0.0,
+        "scatter spacing {spacing} must be finite and above zero"
+    );
+    let half_size = CHUNK_SIZE / 2.0;
+    let min = Vec2::new(chunk_pos.x, chunk_pos.z) - half_size;
+    let cell_size = spacing / 2f32.sqrt();
+    let grid_size = (CHUNK_SIZE / cell_size).ceil() as usize;
+    let cell_of = |point: Vec2| {
+        let cell = ((point - min) / cell_size).as_uvec2();
+        (cell.x as usize, cell.y as usize)
+    };
+
+    // Bridson's algorithm, every grid cell holds at most one point
+    let mut grid: Vec<Option<Vec2>> = vec![None; grid_size * grid_size];
+    let mut points = Vec::new();
+    let first = min + Vec2::new(rng.gen(), rng.gen()) * CHUNK_SIZE;
+    let (x, z) = cell_of(first);
+    grid[x + z * grid_size] = Some(first);
+    points.push(first);
+    let mut active = vec![first];
+    while !active.is_empty() {
+        let index = rng.gen_range(0..active.len());
+        let center = active[index];
+        let candidate = (0..SCATTER_ATTEMPTS).find_map(|_| {
+            let angle = rng.gen_range(0.0..TAU);
+            let distance = rng.gen_range(spacing..spacing * 2.0);
+            let point = center + Vec2::from_angle(angle) * distance;
+            if point.cmplt(min).any() || point.cmpge(min + CHUNK_SIZE).any() {
+                return None;
+            }
+            let (x, z) = cell_of(point);
+            let too_close = (x.saturating_sub(2)..(x + 3).min(grid_size)).any(|nx| {
+                (z.saturating_sub(2)..(z + 3).min(grid_size)).any(|nz| {
+                    grid[nx + nz * grid_size].is_some_and(|other| other.distance(point) < spacing)
+                })
+            });
+            (!too_close).then_some((point, x, z))
+        });
+        match candidate {
+            Some((point, x, z)) => {
+                grid[x + z * grid_size] = Some(point);
+                points.push(point);
+                active.push(point);
+            }
+            None => {
+                active.swap_remove(index);
+            }
+        }
+    }
+
+    // Drop each point onto the highest floor in its column, skipping masked and floorless columns
+    points
+        .into_iter()
+        .filter_map(|point| {
+            let data2d = data_generator.get_data_2d(point.x, point.y);
+            if !mask(&data2d) {
+                return None;
+            }
+            let is_air = |y: f32| data_generator.get_data_3d(&data2d, point.x, point.y, y);
+            let step = data_generator.smallest_cube_size;
+            let mut y = chunk_pos.y + half_size;
+            while y - step >= chunk_pos.y - half_size {
+                if is_air(y) && !is_air(y - step) {
+                    let floor = y - step / 2.0 + data2d.elevation;
+                    return Some(Vec3::new(point.x, floor, point.y));
+                }
+                y -= step;
+            }
+            None
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::WorldGenConfig;
+
+    #[test]
+    fn scattered_points_keep_their_spacing() {
+        let data_generator = DataGenerator::new(&WorldGenConfig::default());
+        let spacing = 0.6;
+        let mut scattered = 0;
+        for x in -4..4 {
+            for y in -2..2 {
+                let chunk = IVec3::new(x, y, 3);
+                let mut rng = ChunkRng::new(data_generator.seed, chunk, 0);
+                let chunk_pos = chunk.as_vec3() * CHUNK_SIZE;
+                let points =
+                    scatter_on_floors(&data_generator, chunk_pos, &mut rng, spacing, |_| true);
+                for (i, a) in points.iter().enumerate() {
+                    for b in &points[i + 1..] {
+                        let distance = Vec2::new(a.x - b.x, a.z - b.z).length();
+                        assert!(distance >= spacing, "{a} and {b} are {distance} apart");
+                    }
+                }
+                scattered += points.len();
+            }
+        }
+        assert!(scattered > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "spacing")]
+    fn zero_spacing_panics() {
+        let data_generator = DataGenerator::new(&WorldGenConfig::default());
+        let mut rng = ChunkRng::new(0, IVec3::ZERO, 0);
+        scatter_on_floors(&data_generator, Vec3::ZERO, &mut rng, 0.0, |_| true);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::chunks::{
+    rooms,
+    world_noise::{Data2D, DataGenerator},
+};
+use bevy::prelude::*;
+
+/// Decides where the world is air, compose these to build custom worlds
+pub trait DensityFn: Send + Sync {
+    /// True if the point is air, `data2d` is the column data at the point's x and z
+    fn is_air(&self, generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool;
+
+    /// Air where either is air
+    fn union<B: DensityFn>(self, other: B) -> Union<Self, B>
+    where
+        Self: Sized,
+    {
+        Union(self, other)
+    }
+
+    /// Air where this is air but other isn't, other fills rock back in
+    fn subtract<B: DensityFn>(self, other: B) -> Subtract<Self, B>
+    where
+        Self: Sized,
+    {
+        Subtract(self, other)
+    }
+
+    /// Offset sample positions by noise, up to `strength` units at a feature size of 1 / `scale`
+    fn warp(self, scale: f32, strength: f32) -> Warp<Self>
+    where
+        Self: Sized,
+    {
+        Warp {
+            inner: self,
+            scale,
+            strength,
+        }
+    }
+
+    /// Tile space every `spacing` units around the origin, zero on an axis leaves it alone
+    fn repeat(self, spacing: Vec3) -> Repeat<Self>
+    where
+        Self: Sized,
+    {
+        Repeat {
+            inner: self,
+            spacing,
+        }
+    }
+}
+
+impl<F> DensityFn for F
+where
+    F: Fn(&DataGenerator, &Data2D, Vec3) -> bool + Send + Sync,
+{
+    fn is_air(&self, generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool {
+        self(generator, data2d, pos)
+    }
+}
+
+/// Offset where the world is sampled by low frequency noise, bending walls into flowing shapes
+#[derive(Reflect, Clone, Copy)]
+pub struct DomainWarp {
+    /// One over the size of the bends
+    pub scale: f32,
+    /// Furthest a sample moves, in world units
+    pub strength: f32,
+}
+
+impl Default for DomainWarp {
+    fn default() -> Self {
+        DomainWarp {
+            scale: 0.03,
+            strength: 6.0,
+        }
+    }
+}
+
+/// The built in world, rooms joined by corridors
+pub fn caves() -> impl DensityFn {
+    Rooms.union(Corridors)
+}
+
+/// Squashed spheres of air at each room position
+pub struct Rooms;
+
+impl DensityFn for Rooms {
+    fn is_air(&self, _generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool {
+        let room_height_smooth = room_height_smooth(data2d, pos.y);
+        let room_dist_3d: f32 = ((pos.x - data2d.room_position[0]).powi(2)
+            + (pos.z - data2d.room_position[1]).powi(2)
+            + (pos.y * room_height_smooth).powi(2))
+        .sqrt();
+        room_dist_3d < data2d.room_size
+    }
+}
+
+/// Winding tunnels along the room grid lines
+pub struct Corridors;
+
+impl DensityFn for Corridors {
+    fn is_air(&self, _generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool {
+        let room_height_smooth = room_height_smooth(data2d, pos.y);
+        let corridor_dist_3d: f32 =
+            (data2d.corridor_dist.powi(2) + (pos.y * room_height_smooth / 2.0).powi(2)).sqrt();
+        corridor_dist_3d < data2d.corridor_width
+    }
+}
+
+/// Higher numbers reduce the height exponentially
+fn room_height_smooth(data2d: &Data2D, y: f32) -> f32 {
+    if y < 0.0 {
+        data2d.room_floor
+    } else {
+        data2d.room_ceiling
+    }
+}
+
+/// Vertical shafts from the middle of some rooms up to the surface
+pub struct Shafts;
+
+impl DensityFn for Shafts {
+    #[allow(clippy::cast_precision_loss)]
+    fn is_air(&self, generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool {
+        if pos.y < 0.0 || pos.y > data2d.surface_height + 1.0 {
+            return false;
+        }
+        // Pick rooms by cell so the whole shaft agrees on whether it exists
+        let (cell_x, cell_z) = rooms::room_cell(generator, pos.x, pos.z);
+        if generator.get_noise2d(cell_x as f32 * 0.7 + 0.5, cell_z as f32 * 0.7 + 0.5) < 0.1 {
+            return false;
+        }
+        let radius = 3.0 + generator.get_noise(pos.y * 0.1) * 1.5;
+        Vec2::new(
+            pos.x - data2d.room_position[0],
+            pos.z - data2d.room_position[1],
+        )
+        .length()
+            < radius
+    }
+}
+
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl DensityFn for Sphere {
+    fn is_air(&self, _generator: &DataGenerator, _data2d: &Data2D, pos: Vec3) -> bool {
+        pos.distance_squared(self.center) < self.radius * self.radius
+    }
+}
+
+pub struct Union<A, B>(A, B);
+
+impl<A: DensityFn, B: DensityFn> DensityFn for Union<A, B> {
+    fn is_air(&self, generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool {
+        self.0.is_air(generator, data2d, pos) || self.1.is_air(generator, data2d, pos)
+    }
+}
+
+pub struct Subtract<A, B>(A, B);
+
+impl<A: DensityFn, B: DensityFn> DensityFn for Subtract<A, B> {
+    fn is_air(&self, generator: &DataGenerator, data2d: &Data2D, pos: Vec3) -> bool {
+        self.0.is_air(generator, data2d, pos) && !self.1.is_air(generator, data2d, pos)
+    }
+}
+
+pub struct Warp<A> {
+    inner: A,
+    scale: f32,
+    strength: f32,
+}
+
+impl<A: DensityFn> DensityFn for Warp<A> {
+    #[allow(clippy::cast_possible_truncation)]
+    fn is_air(&self, generator: &DataGenerator, _data2d: &Data2D, pos: Vec3) -> bool {
+        let scaled = pos.as_dvec3() * f64::from(self.scale);
+        let offset = |seed: f64| {
+            generator
+                .world_noise
+                .get([scaled.x + seed * 1000.0, scaled.y, scaled.z]) as f32
+        };
+        let warped = pos + Vec3::new(offset(11.0), offset(12.0), offset(13.0)) * self.strength;
+        let data2d = generator.get_data_2d(warped.x, warped.z);
+        self.inner.is_air(generator, &data2d, warped)
+    }
+}
+
+pub struct Repeat<A> {
+    inner: A,
+    spacing: Vec3,
+}
+
+impl<A: DensityFn> DensityFn for Repeat<A> {
+    fn is_air(&self, generator: &DataGenerator, _data2d: &Data2D, pos: Vec3) -> bool {
+        let wrapped = pos - (pos / self.spacing).round() * self.spacing;
+        let local = Vec3::select(self.spacing.cmpgt(Vec3::ZERO), wrapped, pos);
+        let data2d = generator.get_data_2d(local.x, local.z);
+        self.inner.is_air(generator, &data2d, local)
+    }
+}
@@ -0,0 +1,66 @@
+use crate::chunks::{streaming, Chunk, ChunkStats};
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    render::primitives::Aabb,
+};
+use std::time::Duration;
+
+pub const CHUNKS: DiagnosticId = DiagnosticId::from_u128(0x3597_eef1_ac6a_47ec_a134_d4bb_010a_eefc);
+pub const CUBES: DiagnosticId = DiagnosticId::from_u128(0xf236_d626_81e5_4dd3_866e_15ab_df47_e4a4);
+pub const TRIANGLES: DiagnosticId =
+    DiagnosticId::from_u128(0x34ae_74be_c272_40fb_8ff8_aaf9_ad7e_c470);
+pub const MESHING_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0xaf01_64a4_1038_4631_a320_a561_0e8f_f8de);
+pub const MESH_ASSETS: DiagnosticId =
+    DiagnosticId::from_u128(0x6c1e_92d4_5b07_4f3a_9e28_c4a1_73d5_0b86);
+
+/// Chunks that took this long or longer to generate are drawn fully red in the heatmap
+const HEATMAP_SLOWEST: Duration = Duration::from_millis(20);
+
+/// Outline every chunk colored by how long it took to generate, green when quick and red when slow
+#[derive(Resource, Default)]
+pub struct GenerationHeatmap(pub bool);
+
+/// Publishes `ChunkStats` as diagnostics, so they show up in `LogDiagnosticsPlugin` and other tools
+pub struct ChunkDiagnosticsPlugin;
+
+impl Plugin for ChunkDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(CHUNKS, "chunks", 20))
+            .register_diagnostic(Diagnostic::new(CUBES, "chunk_cubes", 20))
+            .register_diagnostic(Diagnostic::new(TRIANGLES, "chunk_triangles", 20))
+            .register_diagnostic(
+                Diagnostic::new(MESHING_TIME, "chunk_meshing_time", 20).with_suffix("ms"),
+            )
+            .register_diagnostic(Diagnostic::new(MESH_ASSETS, "chunk_mesh_assets", 20))
+            .init_resource::<GenerationHeatmap>()
+            .add_systems(Update, record_diagnostics.after(streaming::receive_chunks))
+            .add_systems(
+                Update,
+                draw_generation_heatmap.run_if(|heatmap: Res<GenerationHeatmap>| heatmap.0),
+            );
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn record_diagnostics(mut diagnostics: Diagnostics, stats: Res<ChunkStats>) {
+    diagnostics.add_measurement(CHUNKS, || stats.chunks as f64);
+    diagnostics.add_measurement(CUBES, || stats.cubes as f64);
+    diagnostics.add_measurement(TRIANGLES, || stats.triangles as f64);
+    diagnostics.add_measurement(MESHING_TIME, || stats.meshing_time.as_secs_f64() * 1000.0);
+    diagnostics.add_measurement(MESH_ASSETS, || stats.mesh_assets as f64);
+}
+
+/// Boxes drawn around the chunk meshes, so they line up with the cubes raised by their elevation
+fn draw_generation_heatmap(chunks: Query<(&Chunk, &Aabb, &GlobalTransform)>, mut gizmos: Gizmos) {
+    for (chunk, aabb, transform) in &chunks {
+        let cost = (chunk.generation_time.as_secs_f32() / HEATMAP_SLOWEST.as_secs_f32()).min(1.0);
+        let transform = transform.compute_transform();
+        gizmos.cuboid(
+            Transform::from_translation(transform.transform_point(aabb.center.into()))
+                .with_scale(Vec3::from(aabb.half_extents) * 2.0 * transform.scale),
+            Color::rgb(cost, 1.0 - cost, 0.0),
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use crate::chunks::{rooms, world_noise::DataGenerator, StreamingAnchor, CHUNK_SIZE};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// How far past a room's edge an anchor has to go before it counts as entering or leaving,
+/// so standing on the edge doesn't flicker between the two
+const ROOM_HYSTERESIS: f32 = 2.0;
+
+type MovedAnchors = (With<StreamingAnchor>, Changed<GlobalTransform>);
+
+/// Chunks and rooms any anchor has been in, kept across reloads of the same world for minimaps,
+/// copy it out and back in to keep it in a save
+#[derive(Resource, Clone, Default)]
+pub struct ExplorationState {
+    /// Seed of the world explored, changing seeds starts the state over
+    pub seed: u32,
+    pub visited_chunks: HashSet<IVec3>,
+    /// Room cells from `rooms::room_cell`, only counted once inside the room rather than its corridors
+    pub visited_rooms: HashSet<(i32, i32)>,
+}
+
+/// Sent when an anchor walks into a room, for music and quest triggers
+#[derive(Event)]
+pub struct RoomEntered {
+    pub anchor: Entity,
+    /// Room cell from `rooms::room_cell`
+    pub room: (i32, i32),
+}
+
+/// Sent when an anchor leaves the room it last entered
+#[derive(Event)]
+pub struct RoomExited {
+    pub anchor: Entity,
+    pub room: (i32, i32),
+}
+
+pub fn track_exploration(
+    mut exploration: ResMut<ExplorationState>,
+    data_generator: Res<DataGenerator>,
+    anchors: Query<&GlobalTransform, MovedAnchors>,
+) {
+    // Chunks and rooms of another seed are somewhere else entirely
+    if exploration.seed != data_generator.seed {
+        *exploration = ExplorationState {
+            seed: data_generator.seed,
+            ..default()
+        };
+    }
+    for transform in &anchors {
+        let pos = transform.translation();
+        let chunk = (pos / CHUNK_SIZE).round().as_ivec3();
+        // Avoid flagging the resource as changed every frame while standing still in a chunk
+        if !exploration.visited_chunks.contains(&chunk) {
+            exploration.visited_chunks.insert(chunk);
+        }
+
+        let data2d = data_generator.get_data_2d(pos.x, pos.z);
+        let room = rooms::room_cell(&data_generator, pos.x, pos.z);
+        if data2d.room_dist < data2d.room_size && !exploration.visited_rooms.contains(&room) {
+            exploration.visited_rooms.insert(room);
+        }
+    }
+}
+
+/// Send room events as anchors cross room edges, the room they are in is kept per anchor
+pub fn detect_room_changes(
+    data_generator: Res<DataGenerator>,
+    anchors: Query<(Entity, &GlobalTransform), MovedAnchors>,
+    mut removed: RemovedComponents<StreamingAnchor>,
+    mut current_rooms: Local<HashMap<Entity, (i32, i32)>>,
+    mut seed: Local<u32>,
+    mut entered: EventWriter<RoomEntered>,
+    mut exited: EventWriter<RoomExited>,
+) {
+    // The rooms anchors were in are gone with a new seed
+    if *seed != data_generator.seed {
+        *seed = data_generator.seed;
+        for (anchor, room) in current_rooms.drain() {
+            exited.send(RoomExited { anchor, room });
+        }
+    }
+    for (anchor, transform) in &anchors {
+        let pos = transform.translation();
+        let data2d = data_generator.get_data_2d(pos.x, pos.z);
+        let room = rooms::room_cell(&data_generator, pos.x, pos.z);
+
+        if let Some(&current) = current_rooms.get(&anchor) {
+            if current == room && data2d.room_dist < data2d.room_size + ROOM_HYSTERESIS {
+                continue;
+            }
+            current_rooms.remove(&anchor);
+            exited.send(RoomExited {
+                anchor,
+                room: current,
+            });
+        }
+        if data2d.room_dist < data2d.room_size - ROOM_HYSTERESIS {
+            current_rooms.insert(anchor, room);
+            entered.send(RoomEntered { anchor, room });
+        }
+    }
+    for anchor in &mut removed {
+        current_rooms.remove(&anchor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::WorldGenConfig;
+
+    #[test]
+    fn rerolling_the_seed_forgets_discoveries() {
+        let mut app = App::new();
+        app.insert_resource(DataGenerator::new(&WorldGenConfig::default()))
+            .init_resource::<ExplorationState>()
+            .add_systems(Update, track_exploration);
+        app.world
+            .spawn((StreamingAnchor, GlobalTransform::default()));
+        app.update();
+        app.world
+            .resource_mut::<ExplorationState>()
+            .visited_rooms
+            .insert((0, 0));
+        assert!(!app
+            .world
+            .resource::<ExplorationState>()
+            .visited_chunks
+            .is_empty());
+
+        app.insert_resource(DataGenerator::new(&WorldGenConfig {
+            seed: 5,
+            ..default()
+        }));
+        app.update();
+        let exploration = app.world.resource::<ExplorationState>();
+        assert_eq!(exploration.seed, 5);
+        assert!(exploration.visited_chunks.is_empty());
+        assert!(exploration.visited_rooms.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, NoiseFn, OpenSimplex, Perlin, Value};
+use std::sync::Arc;
+
+/// Noise the world is sampled from, in 2D for columns and 3D for everything else
+pub trait WorldNoise: NoiseFn<f64, 2> + NoiseFn<f64, 3> + Send + Sync {}
+
+impl<T: NoiseFn<f64, 2> + NoiseFn<f64, 3> + Send + Sync> WorldNoise for T {}
+
+/// Which noise generator to build the world from, each gives caves a different character
+#[derive(Reflect, Clone, Default)]
+pub enum NoiseBackend {
+    #[default]
+    OpenSimplex,
+    Perlin,
+    /// Blocky interpolated lattice noise
+    Value,
+    /// Octaves of OpenSimplex stacked, rougher walls with more small detail
+    Fbm {
+        octaves: usize,
+        frequency: f64,
+        lacunarity: f64,
+        persistence: f64,
+    },
+}
+
+impl NoiseBackend {
+    /// Fbm with the usual settings, doubling frequency and halving amplitude each octave
+    pub fn fbm(octaves: usize) -> Self {
+        NoiseBackend::Fbm {
+            octaves,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+
+    pub fn build(&self, seed: u32) -> Arc<dyn WorldNoise> {
+        match *self {
+            NoiseBackend::OpenSimplex => Arc::new(OpenSimplex::new(seed)),
+            NoiseBackend::Perlin => Arc::new(Perlin::new(seed)),
+            NoiseBackend::Value => Arc::new(Value::new(seed)),
+            NoiseBackend::Fbm {
+                octaves,
+                frequency,
+                lacunarity,
+                persistence,
+            } => {
+                // Fbm seeds its octaves counting up from the seed, which overflows near the top, so
+                // seed them here wrapping around instead
+                let octaves = octaves.clamp(1, Fbm::<OpenSimplex>::MAX_OCTAVES);
+                let sources = (0..)
+                    .take(octaves)
+                    .map(|octave| OpenSimplex::new(seed.wrapping_add(octave)))
+                    .collect();
+                Arc::new(
+                    Fbm::<OpenSimplex>::new(0)
+                        .set_octaves(octaves)
+                        .set_frequency(frequency)
+                        .set_lacunarity(lacunarity)
+                        .set_persistence(persistence)
+                        .set_sources(sources),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_seeds_build_different_worlds() {
+        let noise = NoiseBackend::fbm(6);
+        let [a, b] = [u32::MAX - 1, u32::MAX].map(|seed| noise.build(seed));
+        let differs = (0..20).any(|i| {
+            let point = [f64::from(i) * 0.37, f64::from(i) * 0.11];
+            (a.get(point) - b.get(point)).abs() > 1e-6
+        });
+        assert!(differs, "the two highest seeds share a world");
+    }
+}
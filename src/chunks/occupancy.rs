@@ -0,0 +1,150 @@
+use crate::chunks::{world_noise::DataGenerator, CHUNK_SIZE, SMALLEST_CUBE_SIZE};
+use bevy::prelude::*;
+
+/// Cells along each side of a chunk, one per smallest cube
+pub const CELLS: usize = (CHUNK_SIZE / SMALLEST_CUBE_SIZE) as usize;
+const WORDS: usize = CELLS.pow(3) / 64;
+const _: () = assert!(
+    CELLS.pow(3).is_multiple_of(64),
+    "cells must fill whole words"
+);
+
+/// Which smallest cube cells of a chunk are rock, packed for external tools.
+///
+/// Cell `(x, y, z)` counting up from the chunk's negative corner is bit
+/// `x + y * CELLS + z * CELLS * CELLS`, stored as bit `index % 64` of word `index / 64`. Solid
+/// cells are set. `colors` holds an index into `palette` for every set bit, in bit order
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChunkOccupancy {
+    pub bits: [u64; WORDS],
+    /// Distinct sRGB colors of the solid cells
+    pub palette: Vec<[u8; 4]>,
+    pub colors: Vec<u16>,
+}
+
+impl ChunkOccupancy {
+    /// Sample the generator at every cell center of the chunk, loaded or not
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn sample(data_generator: &DataGenerator, chunk: IVec3) -> Self {
+        let corner = chunk.as_vec3() * CHUNK_SIZE - CHUNK_SIZE / 2.0 + SMALLEST_CUBE_SIZE / 2.0;
+        let mut occupancy = ChunkOccupancy {
+            bits: [0; WORDS],
+            palette: Vec::new(),
+            colors: Vec::new(),
+        };
+        for index in 0..CELLS.pow(3) {
+            let cell = UVec3::new(
+                (index % CELLS) as u32,
+                (index / CELLS % CELLS) as u32,
+                (index / (CELLS * CELLS)) as u32,
+            );
+            let pos = corner + cell.as_vec3() * SMALLEST_CUBE_SIZE;
+            let data2d = data_generator.get_data_2d(pos.x, pos.z);
+            if data_generator.get_data_3d(&data2d, pos.x, pos.z, pos.y) {
+                continue;
+            }
+            occupancy.bits[index / 64] |= 1 << (index % 64);
+            let color = data_generator
+                .get_data_color(&data2d, pos.x, pos.z, pos.y, SMALLEST_CUBE_SIZE)
+                .color
+                .as_rgba_u8();
+            let palette_index = occupancy
+                .palette
+                .iter()
+                .position(|&entry| entry == color)
+                .unwrap_or_else(|| {
+                    occupancy.palette.push(color);
+                    occupancy.palette.len() - 1
+                });
+            occupancy.colors.push(palette_index as u16);
+        }
+        occupancy
+    }
+
+    pub fn solid_count(&self) -> u32 {
+        self.bits.iter().map(|word| word.count_ones()).sum()
+    }
+
+    /// Little endian bytes: the words, the palette length as a u32, the palette, then the color indices
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for word in self.bits {
+            bytes.extend(word.to_le_bytes());
+        }
+        bytes.extend((self.palette.len() as u32).to_le_bytes());
+        for color in &self.palette {
+            bytes.extend(color);
+        }
+        for color in &self.colors {
+            bytes.extend(color.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Read back what `to_bytes` wrote, None if the bytes are cut short or don't add up
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut rest = bytes;
+        let mut take = |len: usize| {
+            let taken = rest.get(..len)?;
+            rest = &rest[len..];
+            Some(taken)
+        };
+        let mut bits = [0; WORDS];
+        for word in &mut bits {
+            *word = u64::from_le_bytes(take(8)?.try_into().ok()?);
+        }
+        let palette_len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+        let palette: Vec<[u8; 4]> = (0..palette_len)
+            .map(|_| take(4)?.try_into().ok())
+            .collect::<Option<_>>()?;
+        let solid: u32 = bits.iter().map(|word| word.count_ones()).sum();
+        let colors: Vec<u16> = (0..solid)
+            .map(|_| Some(u16::from_le_bytes(take(2)?.try_into().ok()?)))
+            .collect::<Option<_>>()?;
+        let valid = rest.is_empty() && colors.iter().all(|&color| usize::from(color) < palette_len);
+        valid.then_some(ChunkOccupancy {
+            bits,
+            palette,
+            colors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::WorldGenConfig;
+
+    #[test]
+    fn bytes_round_trip() {
+        let data_generator = DataGenerator::new(&WorldGenConfig::default());
+        for chunk in [IVec3::ZERO, IVec3::new(3, -1, 2), IVec3::new(0, 40, 0)] {
+            let occupancy = ChunkOccupancy::sample(&data_generator, chunk);
+            let bytes = occupancy.to_bytes();
+            assert_eq!(ChunkOccupancy::from_bytes(&bytes), Some(occupancy));
+
+            assert_eq!(ChunkOccupancy::from_bytes(&bytes[..bytes.len() - 1]), None);
+            assert_eq!(ChunkOccupancy::from_bytes(&bytes[..WORDS * 8]), None);
+            let mut trailing = bytes.clone();
+            trailing.push(0);
+            assert_eq!(ChunkOccupancy::from_bytes(&trailing), None);
+        }
+    }
+
+    #[test]
+    fn rejects_colors_outside_palette() {
+        let mut bits = [0; WORDS];
+        bits[0] = 1;
+        let occupancy = ChunkOccupancy {
+            bits,
+            palette: vec![[1, 2, 3, 255]],
+            colors: vec![1],
+        };
+        assert_eq!(ChunkOccupancy::from_bytes(&occupancy.to_bytes()), None);
+    }
+}
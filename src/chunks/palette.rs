@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use std::sync::Arc;
+
+// Daltonization matrices from Fidaner et al, written out row by row
+const RGB_TO_LMS: [[f32; 3]; 3] = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.029_956_6, 0.184_309, 1.46709],
+];
+const LMS_TO_RGB: [[f32; 3]; 3] = [
+    [0.080_944_45, -0.130_504_41, 0.116_721_07],
+    [-0.010_248_533, 0.054_019_33, -0.113_614_71],
+    [-0.000_365_296_94, -0.004_121_614_7, 0.693_511_4],
+];
+const PROTANOPIA: [[f32; 3]; 3] = [[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+const DEUTERANOPIA: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.494_207, 0.0, 1.24827], [0.0, 0.0, 1.0]];
+const TRITANOPIA: [[f32; 3]; 3] = [
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [-0.395_913, 0.801_109, 0.0],
+];
+const ERROR_SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+fn mat3_rows(rows: &[[f32; 3]; 3]) -> Mat3 {
+    Mat3::from_cols_array_2d(rows).transpose()
+}
+
+#[derive(Clone, Copy)]
+pub enum ColorBlindness {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+/// Remaps generated colors before they are written into chunk meshes, in nonlinear sRGB
+#[derive(Clone, Default)]
+pub enum Palette {
+    #[default]
+    Identity,
+    Lut(Arc<ColorLut>),
+    Function(Arc<dyn Fn(Vec3) -> Vec3 + Send + Sync>),
+}
+
+impl Palette {
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        match self {
+            Palette::Identity => color,
+            Palette::Lut(lut) => lut.sample(color),
+            Palette::Function(function) => function(color),
+        }
+    }
+
+    /// Shift the colors a color blind viewer confuses into channels they can still tell apart
+    pub fn color_blind(kind: ColorBlindness) -> Self {
+        let simulate = mat3_rows(match kind {
+            ColorBlindness::Protanopia => &PROTANOPIA,
+            ColorBlindness::Deuteranopia => &DEUTERANOPIA,
+            ColorBlindness::Tritanopia => &TRITANOPIA,
+        });
+        let simulate = mat3_rows(&LMS_TO_RGB) * simulate * mat3_rows(&RGB_TO_LMS);
+        let error_shift = mat3_rows(&ERROR_SHIFT);
+        Palette::Function(Arc::new(move |color| {
+            let error = color - simulate * color;
+            (color + error_shift * error).clamp(Vec3::ZERO, Vec3::ONE)
+        }))
+    }
+}
+
+/// 3D lookup table over rgb in 0..1, sampled with trilinear filtering
+pub struct ColorLut {
+    size: usize,
+    colors: Vec<Vec3>,
+}
+
+impl ColorLut {
+    #[allow(clippy::cast_precision_loss)]
+    pub fn from_fn(size: usize, function: impl Fn(Vec3) -> Vec3) -> Self {
+        assert!(size >= 2, "a color lut needs at least 2 entries per axis");
+        let scale = 1.0 / (size - 1) as f32;
+        let mut colors = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    colors.push(function(Vec3::new(r as f32, g as f32, b as f32) * scale));
+                }
+            }
+        }
+        ColorLut { size, colors }
+    }
+
+    fn get(&self, r: usize, g: usize, b: usize) -> Vec3 {
+        self.colors[r + (g + b * self.size) * self.size]
+    }
+
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn sample(&self, color: Vec3) -> Vec3 {
+        let max_index = (self.size - 1) as f32;
+        let pos = color.clamp(Vec3::ZERO, Vec3::ONE) * max_index;
+        let low = pos.floor().min(Vec3::splat(max_index - 1.0));
+        let t = pos - low;
+        let (r, g, b) = (low.x as usize, low.y as usize, low.z as usize);
+
+        let lerp_r = |g, b| self.get(r, g, b).lerp(self.get(r + 1, g, b), t.x);
+        let lerp_g = |b| lerp_r(g, b).lerp(lerp_r(g + 1, b), t.y);
+        lerp_g(b).lerp(lerp_g(b + 1), t.z)
+    }
+}
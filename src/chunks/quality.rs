@@ -0,0 +1,110 @@
+use crate::chunks::{ChunkShading, RenderDistance, WorldGenConfig};
+use bevy::{
+    core_pipeline::experimental::taa::TemporalAntiAliasBundle,
+    pbr::{
+        ScreenSpaceAmbientOcclusionBundle, ScreenSpaceAmbientOcclusionQualityLevel,
+        ScreenSpaceAmbientOcclusionSettings,
+    },
+    prelude::*,
+};
+
+/// Named bundles of graphics settings, changing it applies the preset to the render distance,
+/// chunk shading, 3d cameras and directional lights
+#[derive(Resource, Reflect, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[reflect(Resource)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    /// What the plugin starts with
+    #[default]
+    High,
+    Ultra,
+}
+
+impl QualityPreset {
+    pub fn render_distance(self) -> f32 {
+        match self {
+            QualityPreset::Low => 64.0,
+            QualityPreset::Medium => 96.0,
+            QualityPreset::High => 128.0,
+            QualityPreset::Ultra => 192.0,
+        }
+    }
+
+    pub fn shading(self) -> ChunkShading {
+        match self {
+            QualityPreset::Low => ChunkShading::Flat,
+            _ => ChunkShading::Lit,
+        }
+    }
+
+    /// None turns ambient occlusion off
+    pub fn ambient_occlusion(self) -> Option<ScreenSpaceAmbientOcclusionQualityLevel> {
+        match self {
+            QualityPreset::Low | QualityPreset::Medium => None,
+            QualityPreset::High => Some(ScreenSpaceAmbientOcclusionQualityLevel::Low),
+            QualityPreset::Ultra => Some(ScreenSpaceAmbientOcclusionQualityLevel::High),
+        }
+    }
+
+    pub fn temporal_anti_aliasing(self) -> bool {
+        self != QualityPreset::Low
+    }
+
+    pub fn shadows(self) -> bool {
+        self != QualityPreset::Low
+    }
+
+    /// The next preset up, wrapping back to `Low`
+    pub fn next(self) -> Self {
+        match self {
+            QualityPreset::Low => QualityPreset::Medium,
+            QualityPreset::Medium => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Ultra,
+            QualityPreset::Ultra => QualityPreset::Low,
+        }
+    }
+}
+
+pub fn apply_quality_preset(
+    mut commands: Commands,
+    preset: Res<QualityPreset>,
+    mut render_distance: ResMut<RenderDistance>,
+    mut config: ResMut<WorldGenConfig>,
+    cameras: Query<Entity, With<Camera3d>>,
+    mut lights: Query<&mut DirectionalLight>,
+) {
+    // Also runs on the first frame, so a preset the app inserts before the plugin is applied
+    if !preset.is_changed() {
+        return;
+    }
+    let preset = *preset;
+    render_distance.0 = preset.render_distance();
+    // Only touch the config when it differs, changing it regenerates every chunk
+    if config.shading != preset.shading() {
+        config.shading = preset.shading();
+    }
+
+    for camera in &cameras {
+        let mut camera = commands.entity(camera);
+        // Remove before inserting, both bundles share the depth prepass
+        if preset.ambient_occlusion().is_none() {
+            camera.remove::<ScreenSpaceAmbientOcclusionBundle>();
+        }
+        if !preset.temporal_anti_aliasing() {
+            camera.remove::<TemporalAntiAliasBundle>();
+        }
+        if let Some(quality_level) = preset.ambient_occlusion() {
+            camera.insert(ScreenSpaceAmbientOcclusionBundle {
+                settings: ScreenSpaceAmbientOcclusionSettings { quality_level },
+                ..default()
+            });
+        }
+        if preset.temporal_anti_aliasing() {
+            camera.insert(TemporalAntiAliasBundle::default());
+        }
+    }
+    for mut light in &mut lights {
+        light.shadows_enabled = preset.shadows();
+    }
+}
@@ -1,7 +1,12 @@
 // use crate::chunks::raycast;
-use crate::chunks::Cube;
+use crate::chunks::{palette::Palette, ChunkShading, Cube};
 use bevy::prelude::*;
-use bevy::render::{mesh::Indices, render_resource::PrimitiveTopology};
+use bevy::render::{
+    mesh::{Indices, MeshVertexAttribute, VertexAttributeValues},
+    render_resource::{PrimitiveTopology, VertexFormat},
+};
+use std::collections::HashSet;
+use std::fmt;
 
 const FACES: [[usize; 6]; 6] = [
     [2, 1, 0, 3, 1, 2], // Front face
@@ -19,6 +24,13 @@ const FACES_VERTICES: [[usize; 4]; 6] = [
     [0, 1, 4, 5], // Left face
     [2, 3, 6, 7], // Right face
 ];
+/// Vertex colors packed into bytes, the shader still reads a normalized vec4 at the color location
+const ATTRIBUTE_COLOR_UNORM8: MeshVertexAttribute = MeshVertexAttribute {
+    format: VertexFormat::Unorm8x4,
+    ..Mesh::ATTRIBUTE_COLOR
+};
+#[allow(clippy::cast_possible_truncation)]
+const MAX_VERTICES: usize = u32::MAX as usize;
 const FACE_NORMALS: [Vec3; 6] = [
     Vec3::new(0.0, 0.0, 1.0),  // Front face
     Vec3::new(0.0, 0.0, -1.0), // Back face
@@ -39,34 +51,154 @@ pub struct CubeFace {
 pub struct Face {
     pub vertices: [Vec3; 4],
     pub tris: [[Vec3; 3]; 2],
-    pub color: [f32; 4],
+    pub color: [u8; 4],
+}
+
+/// Stretches cubes on the chunk border outward so lod cracks between chunks are covered
+#[derive(Clone, Copy)]
+pub struct Skirt {
+    /// Elevation the chunk was shifted up by, to find its border again
+    pub elevation: f32,
+    pub half_size: f32,
+    /// How far past the border to reach, at least the gap a coarser neighbour can leave
+    pub depth: f32,
+    /// Elevation varies a little across the chunk, cubes this close to the border still count
+    pub slack: f32,
 }
 
+impl Skirt {
+    /// How much to grow a cube towards the negative and positive sides
+    fn grow(self, cube: &Cube, chunk_pos: Vec3) -> (Vec3, Vec3) {
+        let local = Vec3::from(cube.bounds.center) - chunk_pos - Vec3::Y * self.elevation;
+        let reach = Vec3::from(cube.bounds.half_extents) + self.slack;
+        let depth = Vec3::splat(self.depth);
+        (
+            Vec3::select(
+                (local - reach).cmple(Vec3::splat(-self.half_size)),
+                depth,
+                Vec3::ZERO,
+            ),
+            Vec3::select(
+                (local + reach).cmpge(Vec3::splat(self.half_size)),
+                depth,
+                Vec3::ZERO,
+            ),
+        )
+    }
+}
+
+pub struct ChunkMesh {
+    pub mesh: Mesh,
+    pub n_triangles: usize,
+    /// Few enough vertices for 16 bit indices
+    pub compact_indices: bool,
+    /// Zero area or duplicated triangles left out of the mesh
+    pub stripped_triangles: usize,
+}
+
+/// Why a mesh couldn't be built. Degenerate faces aren't an error, zero area and duplicated
+/// triangles are left out and counted in `ChunkMesh::stripped_triangles` and `Chunk::n_stripped`
+#[derive(Debug)]
+pub enum MeshError {
+    /// No cubes to mesh, callers treat this as an empty lod rather than a failure
+    EmptyChunk,
+    /// A triangle with non finite corners, usually from bad generator output
+    NonFiniteFace { position: Vec3 },
+    /// More vertices than the index format can address
+    TooManyVertices { vertices: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::EmptyChunk => write!(f, "no cubes to mesh"),
+            MeshError::NonFiniteFace { position } => {
+                write!(f, "non finite face at {position}")
+            }
+            MeshError::TooManyVertices { vertices } => {
+                write!(f, "{vertices} vertices is more than {MAX_VERTICES}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
 struct MeshData {
     positions: Vec<[f32; 3]>,
     normals: Vec<[f32; 3]>,
-    colors: Vec<[f32; 4]>,
+    colors: Vec<[u8; 4]>,
     indices: Vec<u32>,
+    stripped_triangles: usize,
 }
 
-pub fn cubes_mesh(cubes: &Vec<Cube>, chunk_pos: Vec3) -> (Mesh, usize) {
-    let (cube_faces, min_pos, max_pos) = generate_cube_faces(cubes, chunk_pos);
+#[allow(clippy::cast_possible_truncation)]
+pub fn cubes_mesh(
+    cubes: &Vec<Cube>,
+    chunk_pos: Vec3,
+    palette: &Palette,
+    shading: ChunkShading,
+    skirt: Option<Skirt>,
+) -> Result<ChunkMesh, MeshError> {
+    if cubes.is_empty() {
+        return Err(MeshError::EmptyChunk);
+    }
+    let (cube_faces, min_pos, max_pos) =
+        generate_cube_faces(cubes, chunk_pos, palette, shading, skirt);
     // let cube_faces = raycast::perform_raycasts(&cube_faces, min_pos, max_pos);
-    let mesh_data = generate_mesh_data(&cube_faces, cubes.len());
+    let vertices = cube_faces
+        .iter()
+        .map(|cube_face| cube_face.faces.len() * 6)
+        .sum();
+    if vertices > MAX_VERTICES {
+        return Err(MeshError::TooManyVertices { vertices });
+    }
+    let mesh_data = generate_mesh_data(&cube_faces, cubes.len())?;
 
     let n_triangles = mesh_data.indices.len() / 3;
+    // Most chunks fit 16 bit indices, halving their index memory
+    let compact_indices = mesh_data.positions.len() <= usize::from(u16::MAX) + 1;
 
     let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
     render_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, mesh_data.positions);
     render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, mesh_data.normals);
-    render_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, mesh_data.colors);
-    render_mesh.set_indices(Some(Indices::U32(mesh_data.indices)));
+    render_mesh.insert_attribute(
+        ATTRIBUTE_COLOR_UNORM8,
+        VertexAttributeValues::Unorm8x4(mesh_data.colors),
+    );
+    let indices = if compact_indices {
+        Indices::U16(
+            mesh_data
+                .indices
+                .iter()
+                .map(|&index| index as u16)
+                .collect(),
+        )
+    } else {
+        Indices::U32(mesh_data.indices)
+    };
+    render_mesh.set_indices(Some(indices));
 
-    (render_mesh, n_triangles)
+    Ok(ChunkMesh {
+        mesh: render_mesh,
+        n_triangles,
+        compact_indices,
+        stripped_triangles: mesh_data.stripped_triangles,
+    })
 }
 
-#[allow(clippy::similar_names)]
-fn generate_cube_faces(cubes: &Vec<Cube>, chunk_pos: Vec3) -> (Vec<CubeFace>, Vec3, Vec3) {
+#[allow(
+    clippy::similar_names,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn generate_cube_faces(
+    cubes: &Vec<Cube>,
+    chunk_pos: Vec3,
+    palette: &Palette,
+    shading: ChunkShading,
+    skirt: Option<Skirt>,
+) -> (Vec<CubeFace>, Vec3, Vec3) {
     let (chunk_x, chunk_y, chunk_z) = chunk_pos.into();
 
     let n_cubes = cubes.len();
@@ -89,13 +221,16 @@ fn generate_cube_faces(cubes: &Vec<Cube>, chunk_pos: Vec3) -> (Vec<CubeFace>, Ve
         let (corner_x, corner_y, corner_z) = cube.pos.into();
         let (real_x, real_y, real_z) = (corner_x - chunk_x, corner_y - chunk_y, corner_z - chunk_z);
 
+        let (grow_minus, grow_plus) = skirt.map_or((Vec3::ZERO, Vec3::ZERO), |skirt| {
+            skirt.grow(cube, chunk_pos)
+        });
         let (real_x_minus, real_x_plus, real_y_minus, real_y_plus, real_z_minus, real_z_plus) = (
-            real_x - half_size,
-            real_x + half_size,
-            real_y - half_size,
-            real_y + half_size,
-            real_z - half_size,
-            real_z + half_size,
+            real_x - half_size - grow_minus.x,
+            real_x + half_size + grow_plus.x,
+            real_y - half_size - grow_minus.y,
+            real_y + half_size + grow_plus.y,
+            real_z - half_size - grow_minus.z,
+            real_z + half_size + grow_plus.z,
         );
 
         let corners = [
@@ -113,11 +248,22 @@ fn generate_cube_faces(cubes: &Vec<Cube>, chunk_pos: Vec3) -> (Vec<CubeFace>, Ve
         min_pos = min_pos.min(Vec3::new(real_x_minus, real_y_minus, real_z_minus));
         max_pos = max_pos.max(Vec3::new(real_x_plus, real_y_plus, real_z_plus));
 
-        let color = [cube.color.x, cube.color.y, cube.color.z, 1.0];
+        // Palettes work on the authored sRGB values, vertex colors are read as linear by the shader
+        let [r, g, b, _] = cube.color.as_rgba_f32();
+        let color = palette.apply(Vec3::new(r, g, b));
+        let color = Color::rgb(color.x, color.y, color.z).as_linear_rgba_f32();
 
         // Loop over each face of the cube
         for (face_index, current_face) in FACES.iter().enumerate() {
             let verts = FACES_VERTICES[face_index];
+            let light = shading.face_light(FACE_NORMALS[face_index]);
+            let color = [
+                color[0] * light,
+                color[1] * light,
+                color[2] * light,
+                color[3],
+            ]
+            .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
             let shift_amount = 0.01;
             let center =
                 (corners[verts[0]] + corners[verts[1]] + corners[verts[2]] + corners[verts[3]])
@@ -151,39 +297,214 @@ fn generate_cube_faces(cubes: &Vec<Cube>, chunk_pos: Vec3) -> (Vec<CubeFace>, Ve
     (cube_faces, min_pos, max_pos)
 }
 
+/// Check a triangle is finite, false if it has no area and should be left out, in debug builds
+/// also check it winds counter clockwise around the declared normal
+fn check_triangle([a, b, c]: [Vec3; 3], normal: Vec3) -> Result<bool, MeshError> {
+    let winding_normal = (b - a).cross(c - a);
+    let area = winding_normal.length_squared();
+    if !area.is_finite() {
+        return Err(MeshError::NonFiniteFace { position: a });
+    }
+    if area <= 0.0 {
+        return Ok(false);
+    }
+    debug_assert!(
+        winding_normal.dot(normal) > 0.0,
+        "triangle {a} {b} {c} winds against face normal {normal}"
+    );
+    Ok(true)
+}
+
 /// Generate the mesh data from the faces
 #[allow(clippy::cast_possible_truncation)]
 #[allow(clippy::cast_sign_loss)]
-fn generate_mesh_data(cube_faces: &Vec<CubeFace>, n_cubes: usize) -> MeshData {
+fn generate_mesh_data(cube_faces: &Vec<CubeFace>, n_cubes: usize) -> Result<MeshData, MeshError> {
     let mut positions: Vec<[f32; 3]> = Vec::with_capacity(n_cubes * 36);
     let mut normals: Vec<[f32; 3]> = Vec::with_capacity(n_cubes * 36);
-    let mut colors: Vec<[f32; 4]> = Vec::with_capacity(n_cubes * 36);
+    let mut colors: Vec<[u8; 4]> = Vec::with_capacity(n_cubes * 36);
     let mut indices: Vec<u32> = Vec::with_capacity(n_cubes * 36);
+    // Overlapping cubes emit the same triangle twice, the winding keeps opposite faces apart
+    let mut seen = HashSet::with_capacity(n_cubes * 12);
+    let mut stripped_triangles = 0;
 
     for cube_face in cube_faces {
         let normal: [f32; 3] = cube_face.normal.into();
         for current_face in &cube_face.faces {
-            let base_index = indices.len() as u32;
-
-            for (tri_index, vertex) in current_face
-                .tris
-                .iter()
-                .flat_map(|tri| tri.iter())
-                .enumerate()
-            {
-                let index = base_index + tri_index as u32;
-                indices.push(index);
-                positions.push((*vertex).into());
-                normals.push(normal);
-                colors.push(current_face.color);
+            for tri in current_face.tris {
+                if !check_triangle(tri, cube_face.normal)?
+                    || !seen.insert(tri.map(|vertex| vertex.to_array().map(f32::to_bits)))
+                {
+                    stripped_triangles += 1;
+                    continue;
+                }
+                for vertex in tri {
+                    indices.push(positions.len() as u32);
+                    positions.push(vertex.into());
+                    normals.push(normal);
+                    colors.push(current_face.color);
+                }
             }
         }
     }
 
-    MeshData {
+    Ok(MeshData {
         positions,
         normals,
         colors,
         indices,
+        stripped_triangles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(pos: Vec3, size: f32, color: Color) -> Cube {
+        Cube {
+            pos,
+            size,
+            color,
+            bounds: bevy::render::primitives::Aabb::from_min_max(
+                pos - size / 2.0,
+                pos + size / 2.0,
+            ),
+        }
+    }
+
+    /// Corners, normals and colors of every triangle in the mesh, in index order
+    fn triangles(mesh: &Mesh) -> Vec<([Vec3; 3], Vec3, [u8; 4])> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("no positions");
+        };
+        let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        else {
+            panic!("no normals");
+        };
+        let Some(VertexAttributeValues::Unorm8x4(colors)) = mesh.attribute(ATTRIBUTE_COLOR_UNORM8)
+        else {
+            panic!("no colors");
+        };
+        let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+        indices
+            .chunks(3)
+            .map(|tri| {
+                (
+                    [tri[0], tri[1], tri[2]].map(|index| Vec3::from(positions[index])),
+                    Vec3::from(normals[tri[0]]),
+                    colors[tri[0]],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cubes_mesh_positions_and_colors() {
+        let chunk_pos = Vec3::new(4.0, -2.0, 6.0);
+        let cubes = vec![
+            cube(chunk_pos + 0.5, 1.0, Color::rgb(1.0, 0.0, 0.0)),
+            cube(chunk_pos - 0.5, 1.0, Color::rgb(0.0, 0.0, 1.0)),
+            cube(
+                chunk_pos + Vec3::new(2.5, 0.5, 0.5),
+                1.0,
+                Color::rgb(0.5, 0.5, 0.5),
+            ),
+        ];
+        let chunk_mesh = cubes_mesh(
+            &cubes,
+            chunk_pos,
+            &Palette::Identity,
+            ChunkShading::Lit,
+            None,
+        )
+        .unwrap();
+        assert_eq!(chunk_mesh.n_triangles, 36);
+        assert_eq!(chunk_mesh.stripped_triangles, 0);
+        assert!(chunk_mesh.compact_indices);
+
+        let triangles = triangles(&chunk_mesh.mesh);
+        // Colors are authored in sRGB and stored linear, mid gray 0.5 is about 0.214
+        for (color, offset) in [
+            ([255, 0, 0, 255], Vec3::ZERO),
+            ([0, 0, 255, 255], Vec3::NEG_ONE),
+            ([55, 55, 55, 255], Vec3::X * 2.0),
+        ] {
+            let corners: Vec<Vec3> = triangles
+                .iter()
+                .filter(|(_, _, tri_color)| *tri_color == color)
+                .flat_map(|(tri, _, _)| *tri)
+                .collect();
+            assert_eq!(corners.len(), 36);
+            // Positions are relative to the chunk, on the corners of the unit cube
+            for corner in corners {
+                let unit = corner - offset;
+                assert!(
+                    unit.to_array().iter().all(|&c| c == 0.0 || c == 1.0),
+                    "{corner} is not a corner of the cube at {offset}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cubes_mesh_winds_around_face_normals() {
+        let chunk_pos = Vec3::new(-2.0, 0.0, 2.0);
+        let cubes: Vec<Cube> = [0.25, 0.5, 1.0, 2.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, size)| {
+                #[allow(clippy::cast_precision_loss)]
+                let offset = Vec3::new(i as f32 * 0.3 - 0.5, 0.1 * i as f32, -0.2);
+                cube(chunk_pos + offset, size, Color::GRAY)
+            })
+            .collect();
+        let skirt = Skirt {
+            elevation: 0.0,
+            half_size: 1.0,
+            depth: 0.25,
+            slack: 0.0625,
+        };
+        for skirt in [None, Some(skirt)] {
+            let chunk_mesh = cubes_mesh(
+                &cubes,
+                chunk_pos,
+                &Palette::Identity,
+                ChunkShading::Flat,
+                skirt,
+            )
+            .unwrap();
+            let triangles = triangles(&chunk_mesh.mesh);
+            for normal in FACE_NORMALS {
+                assert!(triangles.iter().any(|(_, n, _)| *n == normal));
+            }
+            for ([a, b, c], normal, _) in triangles {
+                assert!(FACE_NORMALS.contains(&normal));
+                assert!(
+                    (b - a).cross(c - a).dot(normal) > 0.0,
+                    "triangle {a} {b} {c} winds against {normal}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cubes_mesh_strips_zero_size_cubes() {
+        let cubes = vec![
+            cube(Vec3::ZERO, 1.0, Color::WHITE),
+            cube(Vec3::splat(0.75), 0.0, Color::WHITE),
+        ];
+        let chunk_mesh = cubes_mesh(
+            &cubes,
+            Vec3::ZERO,
+            &Palette::Identity,
+            ChunkShading::Lit,
+            None,
+        )
+        .unwrap();
+        assert_eq!(chunk_mesh.n_triangles, 12);
+        assert_eq!(chunk_mesh.stripped_triangles, 12);
     }
 }
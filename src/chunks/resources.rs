@@ -0,0 +1,103 @@
+use crate::chunks::{world_noise::DataGenerator, CHUNK_SIZE};
+use bevy::prelude::*;
+
+/// Samples along each side of a chunk, columns for minerals and cells for how much is rock
+const SAMPLES: usize = 4;
+
+/// How much of each mineral the rock holds, each from 0 to 1, the same channels that color it,
+/// iron is red, calcium is white and graphite is black
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Minerals {
+    pub calcium: f32,
+    pub graphite: f32,
+    pub iron: f32,
+}
+
+impl Minerals {
+    fn add(self, other: Minerals) -> Minerals {
+        Minerals {
+            calcium: self.calcium + other.calcium,
+            graphite: self.graphite + other.graphite,
+            iron: self.iron + other.iron,
+        }
+    }
+
+    fn scale(self, amount: f32) -> Minerals {
+        Minerals {
+            calcium: self.calcium * amount,
+            graphite: self.graphite * amount,
+            iron: self.iron * amount,
+        }
+    }
+}
+
+/// Mineral density across a chunk, for spawning collectibles in proportion to the local rock
+pub struct ResourceMap {
+    /// Minerals per column, x major, from the chunk's negative corner
+    pub columns: [Minerals; SAMPLES * SAMPLES],
+    /// Fraction of the chunk that is rock, all air chunks hold nothing
+    pub rock: f32,
+}
+
+#[allow(clippy::cast_precision_loss)]
+impl ResourceMap {
+    pub fn sample(data_generator: &DataGenerator, chunk: IVec3) -> Self {
+        let step = CHUNK_SIZE / SAMPLES as f32;
+        let corner = chunk.as_vec3() * CHUNK_SIZE - CHUNK_SIZE / 2.0 + step / 2.0;
+        let mut columns = [Minerals::default(); SAMPLES * SAMPLES];
+        let mut solid = 0;
+        for x in 0..SAMPLES {
+            for z in 0..SAMPLES {
+                let (world_x, world_z) = (corner.x + x as f32 * step, corner.z + z as f32 * step);
+                columns[x * SAMPLES + z] = data_generator.get_minerals(world_x, world_z);
+                let data2d = data_generator.get_data_2d(world_x, world_z);
+                solid += (0..SAMPLES)
+                    .filter(|&y| {
+                        !data_generator.get_data_3d(
+                            &data2d,
+                            world_x,
+                            world_z,
+                            corner.y + y as f32 * step,
+                        )
+                    })
+                    .count();
+            }
+        }
+        ResourceMap {
+            columns,
+            rock: solid as f32 / (SAMPLES * SAMPLES * SAMPLES) as f32,
+        }
+    }
+
+    /// Minerals of the column holding `local`, a position from the chunk's negative corner
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn at(&self, local: Vec2) -> Minerals {
+        let cell = (local / (CHUNK_SIZE / SAMPLES as f32))
+            .floor()
+            .clamp(Vec2::ZERO, Vec2::splat((SAMPLES - 1) as f32));
+        self.columns[cell.x as usize * SAMPLES + cell.y as usize]
+    }
+
+    /// Minerals held in the chunk's rock, averaged over its columns and scaled by how much is rock
+    pub fn total(&self) -> Minerals {
+        self.columns
+            .iter()
+            .fold(Minerals::default(), |sum, &minerals| sum.add(minerals))
+            .scale(self.rock / self.columns.len() as f32)
+    }
+}
+
+/// Minerals held in every chunk from `min` to `max` inclusive, the same for a seed every run so
+/// collectible balance can be compared between seeds and generator changes
+pub fn balance_report(data_generator: &DataGenerator, min: IVec3, max: IVec3) -> Minerals {
+    let mut total = Minerals::default();
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let map = ResourceMap::sample(data_generator, IVec3::new(x, y, z));
+                total = total.add(map.total());
+            }
+        }
+    }
+    total
+}
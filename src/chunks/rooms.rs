@@ -0,0 +1,57 @@
+use crate::chunks::world_noise::{DataGenerator, ROOM_SPACING};
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+const PLACES: [&str; 10] = [
+    "Hollow",
+    "Grotto",
+    "Chamber",
+    "Vault",
+    "Cavern",
+    "Den",
+    "Hall",
+    "Gallery",
+    "Undercroft",
+    "Sump",
+];
+const ADJECTIVES: [&str; 11] = [
+    "Ashen",
+    "Dripping",
+    "Silent",
+    "Amber",
+    "Sunken",
+    "Whispering",
+    "Crooked",
+    "Pale",
+    "Mossy",
+    "Forgotten",
+    "Glittering",
+];
+const NOUNS: [&str; 12] = [
+    "Echoes", "Bones", "Lanterns", "Roots", "Stars", "Tides", "Kings", "Spiders", "Moths",
+    "Embers", "Bells", "Shadows",
+];
+
+/// Grid cell of the room that owns the given position, matches the folding and snapping in
+/// `get_data_2d` so mirrored copies of a room share its cell
+#[allow(clippy::cast_possible_truncation)]
+pub fn room_cell(data_generator: &DataGenerator, x: f32, z: f32) -> (i32, i32) {
+    let (Vec2 { x, y: z }, _) = data_generator.symmetry.fold(Vec2::new(x, z));
+    (
+        (x / ROOM_SPACING).round() as i32,
+        (z / ROOM_SPACING).round() as i32,
+    )
+}
+
+/// Name for the room in the given cell of the world with `seed`, stable across runs
+#[allow(clippy::cast_sign_loss)]
+pub fn room_name(seed: u32, (cell_x, cell_z): (i32, i32)) -> String {
+    let cell = (u64::from(cell_x as u32) << 32) | u64::from(cell_z as u32);
+    // Spread the world seed over all the bits so nearby seeds don't just swap cells around
+    let mut rng = StdRng::seed_from_u64(cell ^ u64::from(seed).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+    let place = PLACES.choose(&mut rng).unwrap();
+    let adjective = ADJECTIVES.choose(&mut rng).unwrap();
+    let noun = NOUNS.choose(&mut rng).unwrap();
+    format!("{place} of {adjective} {noun}")
+}
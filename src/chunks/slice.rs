@@ -0,0 +1,114 @@
+use crate::chunks::world_noise::DataGenerator;
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use rayon::prelude::*;
+
+type ChangedSlices = Or<(Changed<GlobalTransform>, Changed<DensitySlice>)>;
+type Slice = (
+    Entity,
+    Ref<'static, DensitySlice>,
+    &'static GlobalTransform,
+    Option<&'static Handle<StandardMaterial>>,
+);
+
+/// Cross section of the generator drawn on a plane facing the entity's local z, rock in its color
+/// and air see through, sampled straight from the generator so it shows places that haven't
+/// loaded, spawn it with a `SpatialBundle` and move it around to inspect generation
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct DensitySlice {
+    /// World units the plane covers along each side
+    pub size: f32,
+    /// Pixels along each side of the texture
+    pub resolution: u32,
+}
+
+impl Default for DensitySlice {
+    fn default() -> Self {
+        DensitySlice {
+            size: 64.0,
+            resolution: 128,
+        }
+    }
+}
+
+/// Resample slices that moved or changed, and all of them when the world changes, this is slow so
+/// avoid moving them every frame
+pub fn draw_density_slices(
+    mut commands: Commands,
+    data_generator: Res<DataGenerator>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    changed: Query<Entity, ChangedSlices>,
+    slices: Query<Slice>,
+) {
+    for (entity, slice, transform, material) in &slices {
+        if !data_generator.is_changed() && !changed.contains(entity) {
+            continue;
+        }
+        let image = images.add(sample_slice(&data_generator, &slice, transform));
+        let quad = shape::Quad::new(Vec2::splat(slice.size));
+        if let Some(material) = material.and_then(|handle| materials.get_mut(handle)) {
+            material.base_color_texture = Some(image);
+            // The quad is built at the size the slice had, build it again if that changed
+            if slice.is_changed() {
+                commands.entity(entity).insert(meshes.add(quad.into()));
+            }
+            continue;
+        }
+        commands.entity(entity).insert((
+            meshes.add(quad.into()),
+            materials.add(StandardMaterial {
+                base_color_texture: Some(image),
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                double_sided: true,
+                cull_mode: None,
+                ..default()
+            }),
+        ));
+    }
+}
+
+/// Texture of the slice, row 0 is the top of the plane to match the quad's uvs
+#[allow(clippy::cast_precision_loss)]
+fn sample_slice(
+    data_generator: &DataGenerator,
+    slice: &DensitySlice,
+    transform: &GlobalTransform,
+) -> Image {
+    let resolution = slice.resolution.max(1) as usize;
+    let mut data = vec![0; resolution * resolution * 4];
+    data.par_chunks_mut(resolution * 4)
+        .enumerate()
+        .for_each(|(row, pixels)| {
+            let v = 0.5 - (row as f32 + 0.5) / resolution as f32;
+            for (column, pixel) in pixels.chunks_mut(4).enumerate() {
+                let u = (column as f32 + 0.5) / resolution as f32 - 0.5;
+                let pos = transform.transform_point(Vec3::new(u, v, 0.0) * slice.size);
+                // Chunks are drawn raised by their elevation, sample where they were generated
+                let data2d = data_generator.get_data_2d(pos.x, pos.z);
+                let y = pos.y - data2d.elevation;
+                if data_generator.get_data_3d(&data2d, pos.x, pos.z, y) {
+                    continue;
+                }
+                let color = data_generator
+                    .get_data_color(&data2d, pos.x, pos.z, y, data_generator.smallest_cube_size)
+                    .color;
+                pixel.copy_from_slice(&color.as_rgba_u8());
+            }
+        });
+    Image::new(
+        Extent3d {
+            width: resolution as u32,
+            height: resolution as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
@@ -0,0 +1,1065 @@
+use crate::chunks::{
+    generate_chunk,
+    palette::Palette,
+    world_noise::{DataGenerator, MAX_ELEVATION},
+    Chunk, ChunkShading, RenderDistance, VoxelState, WorldGenConfig, CHUNK_SIZE,
+};
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::pbr::{NotShadowCaster, NotShadowReceiver};
+use bevy::prelude::*;
+use bevy::reflect::{
+    serde::{ReflectSerializer, UntypedReflectDeserializer},
+    TypeRegistryArc,
+};
+use bevy::render::{primitives::Aabb, view::RenderLayers};
+use serde::de::DeserializeSeed;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DIRECTIONS: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+/// Time allowed per frame for turning finished chunks into entities
+const SPAWN_BUDGET: Duration = Duration::from_millis(4);
+/// Failed chunks are retried this many times, waiting twice as long each time
+const MAX_RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+/// Chunks wanting this lod or coarser drop the lods finer than `KEEP_FROM_LOD` along with their cubes
+const DROP_AT_LOD: usize = 3;
+/// Coarsest detail kept for far chunks, 4 times the smallest cubes, wanting anything finer regenerates the chunk
+const KEEP_FROM_LOD: usize = 2;
+
+/// Shafts bring skylight down to the rooms around y 0, chunks entirely this far below never see it
+const SKYLIGHT_DEPTH: f32 = 16.0;
+/// Folder panicking chunks are written to, relative to the working directory
+const CRASH_DUMP_DIR: &str = "chunk_dumps";
+
+type ChunkResult = (u32, IVec3, Duration, Result<Chunk, String>);
+
+/// Entities that chunks are streamed around, a chunk stays loaded while any anchor can reach it,
+/// it only needs a transform so it can sit on an XR rig's root instead of a camera
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct StreamingAnchor;
+
+/// Totals for the currently loaded chunks
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct ChunkStats {
+    pub chunks: usize,
+    pub cubes: usize,
+    pub triangles: usize,
+    pub queued: usize,
+    pub generating: usize,
+    /// Time spent generating the chunks received this frame, summed over all threads
+    pub meshing_time: Duration,
+    /// Loaded chunks whose meshes fit 16 bit indices
+    pub compact_chunks: usize,
+    /// Zero area or duplicated triangles left out of loaded chunk meshes
+    pub stripped_triangles: usize,
+    /// Chunk meshes alive in `Assets<Mesh>`, more than `chunks` means meshes are leaking
+    pub mesh_assets: usize,
+}
+
+#[derive(Resource)]
+pub struct ChunkMaterial(pub Handle<StandardMaterial>);
+
+/// Owns the mesh shown by each chunk entity and frees it when the chunk unloads or swaps lod,
+/// even if something else cloned the handle
+#[derive(Resource, Default)]
+pub struct ChunkMeshes {
+    handles: HashMap<Entity, Handle<Mesh>>,
+    /// Weak handles to every mesh made for a chunk, kept apart from `handles` so meshes the
+    /// streaming lost track of are still counted while they stay in `Assets<Mesh>`
+    created: HashSet<Handle<Mesh>>,
+}
+
+impl ChunkMeshes {
+    /// Add `mesh` as the mesh of `entity`, freeing the one it showed before
+    fn insert(&mut self, entity: Entity, mesh: Mesh, meshes: &mut Assets<Mesh>) -> Handle<Mesh> {
+        let handle = meshes.add(mesh);
+        self.created.insert(handle.clone_weak());
+        if let Some(old) = self.handles.insert(entity, handle.clone()) {
+            meshes.remove(&old);
+        }
+        handle
+    }
+
+    fn release(&mut self, entity: Entity, meshes: &mut Assets<Mesh>) {
+        if let Some(handle) = self.handles.remove(&entity) {
+            meshes.remove(&handle);
+        }
+    }
+}
+
+/// Send to throw away a chunk and build it again from the seed, ignored if it isn't loaded
+#[derive(Event)]
+pub struct RegenerateChunk(pub IVec3);
+
+/// Seconds newly streamed chunks take to grow in, softening pop in, zero turns it off
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ChunkSpawnAnimation(pub f32);
+
+impl Default for ChunkSpawnAnimation {
+    fn default() -> Self {
+        ChunkSpawnAnimation(0.3)
+    }
+}
+
+/// Everything needed to generate a chunk again, written out when generating it panics. The density,
+/// decorators and palette are code so they can't be saved, the dump only records what was set
+#[derive(Reflect)]
+pub struct ChunkCrashDump {
+    pub chunk: IVec3,
+    pub error: String,
+    /// Loads with the built in caves, no decorators and the identity palette
+    pub config: WorldGenConfig,
+    /// A custom density was set, put it back in `config` before reproducing
+    pub custom_density: bool,
+    pub decorators: usize,
+    /// Which palette variant was in use
+    pub palette: String,
+}
+
+impl ChunkCrashDump {
+    /// Read a dump written by the chunk streaming, reproduce the chunk with
+    /// `generate_chunk(&DataGenerator::new(&dump.config), &dump.config, dump.chunk)`
+    pub fn load(registry: &TypeRegistryArc, path: &Path) -> Result<Self, String> {
+        let ron = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut deserializer = ron::Deserializer::from_str(&ron).map_err(|err| err.to_string())?;
+        let reflected = UntypedReflectDeserializer::new(&registry.read())
+            .deserialize(&mut deserializer)
+            .map_err(|err| err.to_string())?;
+        ChunkCrashDump::from_reflect(&*reflected)
+            .ok_or_else(|| format!("{} is not a chunk dump", path.display()))
+    }
+}
+
+/// Write the chunk and world settings to a ron file so the panic can be reproduced from a report
+fn write_crash_dump(
+    registry: &TypeRegistryArc,
+    chunk: IVec3,
+    error: &str,
+    config: &WorldGenConfig,
+) -> Result<PathBuf, String> {
+    let dump = ChunkCrashDump {
+        chunk,
+        error: error.to_string(),
+        config: config.clone(),
+        custom_density: config.density.is_some(),
+        decorators: config.decorators.len(),
+        palette: match config.palette {
+            Palette::Identity => "Identity",
+            Palette::Lut(_) => "Lut",
+            Palette::Function(_) => "Function",
+        }
+        .to_string(),
+    };
+    let registry = registry.read();
+    let ron = bevy::scene::serialize_ron(ReflectSerializer::new(&dump, &registry))
+        .map_err(|err| err.to_string())?;
+    let path = PathBuf::from(CRASH_DUMP_DIR).join(format!(
+        "seed{}_{}_{}_{}.ron",
+        config.seed, chunk.x, chunk.y, chunk.z
+    ));
+    std::fs::create_dir_all(CRASH_DUMP_DIR).map_err(|err| err.to_string())?;
+    std::fs::write(&path, ron).map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+/// Layers chunk entities render on, move them off the default layer to composite the world with
+/// other scenes or show it only to some cameras, like a minimap or portal view
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct ChunkRenderLayers(pub RenderLayers);
+
+/// Seconds since the chunk spawned, removed once it is full size
+#[derive(Component)]
+pub struct Appearing(f32);
+
+/// Sent when generating a chunk panics or produces invalid cubes
+#[derive(Event)]
+pub struct ChunkGenFailed {
+    pub chunk: IVec3,
+    pub attempt: u32,
+    pub error: String,
+    /// If false the chunk was given up on and left empty
+    pub will_retry: bool,
+}
+
+enum ChunkState {
+    Queued,
+    Generating,
+    Generated {
+        entity: Option<Entity>,
+        blocking: bool,
+        lod: Option<usize>,
+    },
+}
+
+struct ChunkEntry {
+    state: ChunkState,
+    /// Number of anchors that can reach this chunk
+    anchors: usize,
+    failures: u32,
+    /// Coarse entity left showing while the chunk regenerates with full detail
+    replacing: Option<Entity>,
+    /// The chunk was open before it went back in the queue, keep exploring through it until it
+    /// arrives so the chunks behind it aren't unloaded and streamed in again
+    passable: bool,
+}
+
+/// One run of the world, chunk tasks from an ended session skip their work and are dropped on arrival
+#[derive(Clone, Default)]
+struct WorldSession {
+    id: u32,
+    ended: Arc<AtomicBool>,
+}
+
+impl WorldSession {
+    /// End this session, telling its tasks to stop, and start the next one
+    fn next(&self) -> WorldSession {
+        self.ended.store(true, Ordering::Relaxed);
+        WorldSession {
+            id: self.id.wrapping_add(1),
+            ended: default(),
+        }
+    }
+}
+
+struct AnchorState {
+    chunk: IVec3,
+    reachable: HashSet<IVec3>,
+}
+
+#[derive(Resource)]
+pub struct ChunkMap {
+    chunks: HashMap<IVec3, ChunkEntry>,
+    anchors: HashMap<Entity, AnchorState>,
+    queue: VecDeque<IVec3>,
+    /// Failed chunks waiting for their backoff before going back in the queue
+    retries: Vec<(Instant, IVec3)>,
+    in_flight: usize,
+    lods_dirty: bool,
+    /// Render distance in chunks
+    radius: f32,
+    /// Lods each chunk is generated with, the last is wanted at the edge of the render distance
+    n_lods: usize,
+    /// Highest chunk layer to explore, so the open sky over a surface isn't loaded
+    max_y: i32,
+    /// Replaced on reload so chunks generated with old settings are dropped when they arrive
+    session: WorldSession,
+    sender: Sender<ChunkResult>,
+    receiver: Mutex<Receiver<ChunkResult>>,
+}
+
+impl Default for ChunkMap {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        ChunkMap {
+            chunks: HashMap::new(),
+            anchors: HashMap::new(),
+            queue: VecDeque::new(),
+            retries: Vec::new(),
+            in_flight: 0,
+            lods_dirty: false,
+            radius: 0.0,
+            n_lods: 1,
+            max_y: i32::MAX,
+            session: default(),
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+}
+
+impl ChunkMap {
+    /// Entity of a loaded chunk, None while it is generating or if it has no cubes
+    pub fn chunk_entity(&self, pos: IVec3) -> Option<Entity> {
+        match self.chunks.get(&pos)?.state {
+            ChunkState::Generated { entity, .. } => entity,
+            _ => None,
+        }
+    }
+
+    /// Take a reference to a chunk for an anchor, queueing it for generation if it is new
+    fn add_ref(&mut self, pos: IVec3) {
+        let entry = self.chunks.entry(pos).or_insert_with(|| {
+            self.queue.push_back(pos);
+            ChunkEntry {
+                state: ChunkState::Queued,
+                anchors: 0,
+                failures: 0,
+                replacing: None,
+                passable: false,
+            }
+        });
+        entry.anchors += 1;
+    }
+
+    /// Drop a reference to a chunk, unloading it once no anchor needs it
+    fn release(
+        &mut self,
+        pos: IVec3,
+        commands: &mut Commands,
+        chunks: &Query<&Chunk>,
+        stats: &mut ChunkStats,
+    ) {
+        let Some(entry) = self.chunks.get_mut(&pos) else {
+            return;
+        };
+        entry.anchors -= 1;
+        if entry.anchors > 0 {
+            return;
+        }
+        let Some(entry) = self.chunks.remove(&pos) else {
+            return;
+        };
+        if let ChunkState::Generated {
+            entity: Some(entity),
+            ..
+        } = entry.state
+        {
+            despawn_chunk(entity, commands, chunks, stats);
+        }
+        if let Some(entity) = entry.replacing {
+            despawn_chunk(entity, commands, chunks, stats);
+        }
+    }
+
+    /// Unload every chunk and end the session, anchors then stream the world back in from scratch
+    fn reload(&mut self, commands: &mut Commands, stats: &mut ChunkStats) {
+        for entry in self.chunks.values() {
+            if let ChunkState::Generated {
+                entity: Some(entity),
+                ..
+            } = entry.state
+            {
+                // Chunks may already be gone if the game despawned its scene first
+                if let Some(entity) = commands.get_entity(entity) {
+                    entity.despawn_recursive();
+                }
+            }
+            if let Some(entity) = entry
+                .replacing
+                .and_then(|entity| commands.get_entity(entity))
+            {
+                entity.despawn_recursive();
+            }
+        }
+        self.chunks.clear();
+        self.anchors.clear();
+        self.queue.clear();
+        self.retries.clear();
+        self.session = self.session.next();
+        stats.chunks = 0;
+        stats.cubes = 0;
+        stats.triangles = 0;
+        stats.compact_chunks = 0;
+        stats.stripped_triangles = 0;
+    }
+}
+
+fn despawn_chunk(
+    entity: Entity,
+    commands: &mut Commands,
+    chunks: &Query<&Chunk>,
+    stats: &mut ChunkStats,
+) {
+    if let Ok(chunk) = chunks.get(entity) {
+        stats.chunks -= 1;
+        stats.cubes -= chunk.n_cubes;
+        stats.triangles -= chunk.n_triangles;
+        stats.compact_chunks -= usize::from(chunk.compact_indices);
+        stats.stripped_triangles -= chunk.n_stripped;
+    }
+    if let Some(entity) = commands.get_entity(entity) {
+        entity.despawn_recursive();
+    }
+}
+
+/// Flood fill from `frontier` through generated open chunks within `radius` chunks of `center`
+/// and no higher than `max_y`
+fn flood_fill(
+    chunks: &HashMap<IVec3, ChunkEntry>,
+    radius: f32,
+    max_y: i32,
+    center: IVec3,
+    frontier: Vec<IVec3>,
+    reachable: &mut HashSet<IVec3>,
+) -> Vec<IVec3> {
+    let mut added = Vec::new();
+    let mut queue = frontier;
+    while let Some(pos) = queue.pop() {
+        for direction in DIRECTIONS {
+            let neighbor = pos + direction;
+            let distance = (neighbor - center).as_vec3().length();
+            if distance > radius || neighbor.y > max_y || !reachable.insert(neighbor) {
+                continue;
+            }
+            added.push(neighbor);
+            // Only explore through chunks we know are not blocking
+            let open = chunks.get(&neighbor).is_some_and(|entry| {
+                entry.passable
+                    || matches!(
+                        entry.state,
+                        ChunkState::Generated {
+                            blocking: false,
+                            ..
+                        }
+                    )
+            });
+            if open {
+                queue.push(neighbor);
+            }
+        }
+    }
+    added
+}
+
+/// Get wanted lod based on distance, if close to an anchor it should be 0, if close to `radius` it should be `n_lods`
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn target_lod(
+    anchors: &HashMap<Entity, AnchorState>,
+    radius: f32,
+    n_lods: usize,
+    chunk_pos: Vec3,
+) -> usize {
+    let distance = anchors
+        .values()
+        .map(|anchor| chunk_pos.distance(anchor.chunk.as_vec3() * CHUNK_SIZE))
+        .fold(f32::INFINITY, f32::min);
+    (distance / radius * n_lods as f32).floor() as usize
+}
+
+pub fn setup_chunk_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ChunkMaterial(materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        ..default()
+    })));
+}
+
+/// Switch the chunk material between lit and flat when the shading setting changes
+pub fn sync_chunk_material(
+    config: Res<WorldGenConfig>,
+    material: Res<ChunkMaterial>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !config.is_changed() {
+        return;
+    }
+    let flat = config.shading == ChunkShading::Flat;
+    if let Some(material) = materials.get_mut(&material.0) {
+        if material.unlit != flat {
+            material.unlit = flat;
+        }
+    }
+}
+
+/// Move loaded chunks to the new layers when `ChunkRenderLayers` changes
+pub fn sync_chunk_render_layers(
+    render_layers: Res<ChunkRenderLayers>,
+    mut chunks: Query<&mut RenderLayers, With<Chunk>>,
+) {
+    if !render_layers.is_changed() {
+        return;
+    }
+    for mut layers in &mut chunks {
+        *layers = render_layers.0;
+    }
+}
+
+/// Move on to `InGame` once the chunks around the anchors have all arrived
+pub fn finish_loading(stats: Res<ChunkStats>, mut next_state: ResMut<NextState<VoxelState>>) {
+    if stats.chunks > 0 && stats.queued == 0 && stats.generating == 0 {
+        next_state.set(VoxelState::InGame);
+    }
+}
+
+/// Unload the world and discard chunks still generating, add it to `OnExit` of the game's state
+/// so nothing arrives after the scene is gone, anchors stream it back in when they return
+pub fn teardown_world(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    mut stats: ResMut<ChunkStats>,
+) {
+    map.reload(&mut commands, &mut stats);
+}
+
+/// Regenerate everything when the world settings change
+pub fn reload_on_config_change(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    mut stats: ResMut<ChunkStats>,
+    mut data_generator: ResMut<DataGenerator>,
+    config: Res<WorldGenConfig>,
+) {
+    if config.is_changed() && !config.is_added() {
+        if let Err(error) = config.validate() {
+            error!("Ignoring invalid world settings: {error}");
+            return;
+        }
+        *data_generator = DataGenerator::new(&config);
+        map.reload(&mut commands, &mut stats);
+    }
+}
+
+/// Recompute which chunks each anchor can reach when it moves into a new chunk
+#[allow(clippy::cast_possible_truncation)]
+pub fn update_anchors(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    mut stats: ResMut<ChunkStats>,
+    anchors: Query<(Entity, &GlobalTransform), With<StreamingAnchor>>,
+    chunks: Query<&Chunk>,
+    render_distance: Res<RenderDistance>,
+    data_generator: Res<DataGenerator>,
+) {
+    // A new render distance changes what every anchor can reach
+    let radius_changed = render_distance.is_changed();
+    if radius_changed {
+        map.radius = render_distance.0 / CHUNK_SIZE;
+    }
+    // Anchors are reset when the generator is rebuilt, so they pick this up on their next fill
+    if data_generator.is_changed() {
+        map.max_y = data_generator
+            .surface_top()
+            .map_or(i32::MAX, |top| (top / CHUNK_SIZE).ceil() as i32);
+        map.n_lods = data_generator.n_lods();
+    }
+
+    // Forget anchors that were despawned or lost the component
+    let removed: Vec<Entity> = map
+        .anchors
+        .keys()
+        .filter(|&&entity| !anchors.contains(entity))
+        .copied()
+        .collect();
+    for entity in removed {
+        let anchor = map.anchors.remove(&entity).unwrap();
+        for pos in anchor.reachable {
+            map.release(pos, &mut commands, &chunks, &mut stats);
+        }
+        map.lods_dirty = true;
+    }
+
+    for (entity, transform) in &anchors {
+        let chunk = (transform.translation() / CHUNK_SIZE).round().as_ivec3();
+        if map
+            .anchors
+            .get(&entity)
+            .is_some_and(|anchor| anchor.chunk == chunk)
+            && !radius_changed
+        {
+            continue;
+        }
+
+        let mut reachable = HashSet::from([chunk]);
+        flood_fill(
+            &map.chunks,
+            map.radius,
+            map.max_y,
+            chunk,
+            vec![chunk],
+            &mut reachable,
+        );
+
+        // Take new references before releasing old ones so shared chunks never unload
+        let previous = map.anchors.remove(&entity).map(|anchor| anchor.reachable);
+        for &pos in &reachable {
+            if !previous.as_ref().is_some_and(|set| set.contains(&pos)) {
+                map.add_ref(pos);
+            }
+        }
+        for pos in previous.unwrap_or_default() {
+            if !reachable.contains(&pos) {
+                map.release(pos, &mut commands, &chunks, &mut stats);
+            }
+        }
+        map.anchors.insert(entity, AnchorState { chunk, reachable });
+        map.lods_dirty = true;
+    }
+}
+
+pub fn regenerate_chunks(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    mut stats: ResMut<ChunkStats>,
+    mut events: EventReader<RegenerateChunk>,
+    chunks: Query<&Chunk>,
+) {
+    for &RegenerateChunk(pos) in &mut events {
+        // Chunks already generating will arrive fresh anyway
+        let Some(entry) = map.chunks.get_mut(&pos) else {
+            continue;
+        };
+        if matches!(entry.state, ChunkState::Generating) {
+            continue;
+        }
+        if let ChunkState::Generated {
+            entity: Some(entity),
+            ..
+        } = entry.state
+        {
+            despawn_chunk(entity, &mut commands, &chunks, &mut stats);
+        }
+        if let ChunkState::Generated { blocking, .. } = entry.state {
+            entry.passable = !blocking;
+        }
+        entry.state = ChunkState::Queued;
+        entry.failures = 0;
+        // Jump the queue, someone is waiting on this one
+        map.queue.push_front(pos);
+    }
+}
+
+/// Start generating queued chunks on the rayon pool, keeping one in flight per thread
+pub fn queue_chunks(
+    mut map: ResMut<ChunkMap>,
+    mut stats: ResMut<ChunkStats>,
+    data_generator: Res<DataGenerator>,
+    config: Res<WorldGenConfig>,
+    type_registry: Res<AppTypeRegistry>,
+) {
+    // Put failed chunks whose backoff has passed back in the queue
+    let now = Instant::now();
+    let ChunkMap { queue, retries, .. } = map.as_mut();
+    retries.retain(|&(retry_at, pos)| {
+        if retry_at > now {
+            return true;
+        }
+        queue.push_back(pos);
+        false
+    });
+
+    let max_in_flight = rayon::current_num_threads();
+    while map.in_flight < max_in_flight {
+        let Some(pos) = map.queue.pop_front() else {
+            break;
+        };
+        let Some(entry) = map.chunks.get_mut(&pos) else {
+            continue;
+        };
+        if !matches!(entry.state, ChunkState::Queued) {
+            continue;
+        }
+        entry.state = ChunkState::Generating;
+        map.in_flight += 1;
+
+        let sender = map.sender.clone();
+        let session = map.session.clone();
+        let data_generator = data_generator.clone();
+        let config = config.clone();
+        let type_registry = type_registry.0.clone();
+        rayon::spawn(move || {
+            if session.ended.load(Ordering::Relaxed) {
+                let _ = sender.send((
+                    session.id,
+                    pos,
+                    Duration::ZERO,
+                    Err("cancelled".to_string()),
+                ));
+                return;
+            }
+            let start = Instant::now();
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                generate_chunk(&data_generator, &config, pos)
+            }))
+            .map_err(|payload| {
+                let error = payload
+                    .downcast_ref::<&str>()
+                    .map(ToString::to_string)
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                match write_crash_dump(&type_registry, pos, &error, &config) {
+                    Ok(path) => format!("{error}, dumped to {}", path.display()),
+                    Err(err) => format!("{error}, could not write a dump: {err}"),
+                }
+            })
+            .and_then(|result| result.map_err(|error| error.to_string()))
+            .and_then(|chunk| {
+                if chunk.cubes.iter().all(|cube| cube.pos.is_finite()) {
+                    Ok(chunk)
+                } else {
+                    Err("non finite cube position".to_string())
+                }
+            });
+            // The map owns the receiver, if it is gone there is nobody left to tell
+            let _ = sender.send((session.id, pos, start.elapsed(), result));
+        });
+    }
+    stats.queued = map.queue.len();
+    stats.generating = map.in_flight;
+}
+
+/// Spawn finished chunks within the frame budget and continue exploring through them
+#[allow(clippy::too_many_arguments)]
+pub fn receive_chunks(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    mut stats: ResMut<ChunkStats>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    mut failed_events: EventWriter<ChunkGenFailed>,
+    material: Res<ChunkMaterial>,
+    render_layers: Res<ChunkRenderLayers>,
+    chunks: Query<&Chunk>,
+) {
+    let start = Instant::now();
+    stats.meshing_time = Duration::ZERO;
+    while start.elapsed() < SPAWN_BUDGET {
+        let Ok((session, pos, meshing_time, result)) = map.receiver.lock().unwrap().try_recv()
+        else {
+            break;
+        };
+        map.in_flight -= 1;
+        stats.meshing_time += meshing_time;
+        if session != map.session.id {
+            continue;
+        }
+
+        // Chunk was unloaded while it was generating
+        let Some(entry) = map.chunks.get_mut(&pos) else {
+            continue;
+        };
+        if matches!(entry.state, ChunkState::Generated { .. }) {
+            continue;
+        }
+
+        let mut chunk = match result {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                entry.failures += 1;
+                let attempt = entry.failures;
+                let will_retry = attempt <= MAX_RETRIES;
+                warn!("Chunk {pos} failed to generate on attempt {attempt}: {error}");
+                if will_retry {
+                    entry.state = ChunkState::Queued;
+                    let backoff = RETRY_BACKOFF * 2u32.pow(attempt - 1);
+                    map.retries.push((Instant::now() + backoff, pos));
+                } else {
+                    // Give up and leave it empty, but don't explore past what we know nothing about
+                    entry.passable = false;
+                    entry.state = ChunkState::Generated {
+                        entity: None,
+                        blocking: true,
+                        lod: None,
+                    };
+                    if let Some(entity) = entry.replacing.take() {
+                        despawn_chunk(entity, &mut commands, &chunks, &mut stats);
+                    }
+                }
+                failed_events.send(ChunkGenFailed {
+                    chunk: pos,
+                    attempt,
+                    error,
+                    will_retry,
+                });
+                continue;
+            }
+        };
+
+        chunk.generation_time = meshing_time;
+        let blocking = chunk.n_cubes == 1;
+        let mut entity = None;
+        let mut lod = None;
+        // If chunk is empty don't render it
+        if chunk.n_cubes > 0 {
+            let target_lod = target_lod(&map.anchors, map.radius, map.n_lods, chunk.chunk_pos);
+            let mut chunk_entity = commands.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(chunk.chunk_pos)),
+                render_layers.0,
+            ));
+            // Chunks swapping in finer detail take over from one already showing, so don't grow them
+            if map.chunks[&pos].replacing.is_none() {
+                chunk_entity.insert(Appearing(0.0));
+            }
+            // Render out the target_lod if it exists
+            if let Some(mesh) = chunk.lod(target_lod) {
+                let handle = chunk_meshes.insert(chunk_entity.id(), mesh.clone(), &mut meshes);
+                chunk_entity.insert((handle, material.0.clone()));
+                lod = Some(target_lod);
+            }
+            stats.chunks += 1;
+            stats.cubes += chunk.n_cubes;
+            stats.triangles += chunk.n_triangles;
+            stats.compact_chunks += usize::from(chunk.compact_indices);
+            stats.stripped_triangles += chunk.n_stripped;
+            entity = Some(chunk_entity.insert(chunk).id());
+        }
+        let entry = map.chunks.get_mut(&pos).unwrap();
+        entry.passable = false;
+        entry.state = ChunkState::Generated {
+            entity,
+            blocking,
+            lod,
+        };
+        if let Some(entity) = entry.replacing.take() {
+            despawn_chunk(entity, &mut commands, &chunks, &mut stats);
+        }
+
+        // If chunk is blocking, don't explore it further
+        if blocking {
+            continue;
+        }
+        let mut added = Vec::new();
+        let ChunkMap {
+            chunks,
+            anchors,
+            radius,
+            max_y,
+            ..
+        } = map.as_mut();
+        for anchor in anchors.values_mut() {
+            if anchor.reachable.contains(&pos) {
+                added.extend(flood_fill(
+                    chunks,
+                    *radius,
+                    *max_y,
+                    anchor.chunk,
+                    vec![pos],
+                    &mut anchor.reachable,
+                ));
+            }
+        }
+        for pos in added {
+            map.add_ref(pos);
+        }
+    }
+}
+
+/// Swap chunk meshes to the lod matching their distance after anchors move, far chunks drop their
+/// fine detail and get it regenerated when an anchor comes back
+pub fn update_lods(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    material: Res<ChunkMaterial>,
+    mut chunks: Query<&mut Chunk>,
+) {
+    if !map.lods_dirty {
+        return;
+    }
+    map.lods_dirty = false;
+
+    let ChunkMap {
+        chunks: entries,
+        anchors,
+        queue,
+        radius,
+        n_lods,
+        ..
+    } = map.as_mut();
+    for (&pos, entry) in entries.iter_mut() {
+        let ChunkState::Generated {
+            entity: Some(entity),
+            blocking,
+            lod,
+        } = &mut entry.state
+        else {
+            continue;
+        };
+        let Ok(mut chunk) = chunks.get_mut(*entity) else {
+            continue;
+        };
+        let target_lod = target_lod(anchors, *radius, *n_lods, chunk.chunk_pos);
+        if target_lod < chunk.first_lod {
+            entry.replacing = Some(*entity);
+            entry.passable = !*blocking;
+            entry.state = ChunkState::Queued;
+            queue.push_back(pos);
+            continue;
+        }
+        // Worlds with few lods have nothing past KEEP_FROM_LOD to fall back on, so keep them whole
+        if target_lod >= DROP_AT_LOD
+            && chunk.first_lod < KEEP_FROM_LOD
+            && chunk.first_lod + chunk.lods.len() > KEEP_FROM_LOD
+        {
+            let dropped = (KEEP_FROM_LOD - chunk.first_lod).min(chunk.lods.len());
+            chunk.lods.drain(..dropped);
+            chunk.first_lod = KEEP_FROM_LOD;
+            chunk.cubes = Vec::new();
+        }
+        if *lod == Some(target_lod) {
+            continue;
+        }
+        if let Some(mesh) = chunk.lod(target_lod) {
+            let handle = chunk_meshes.insert(*entity, mesh.clone(), &mut meshes);
+            // Bevy only computes bounds for meshes without them, coarse lods can poke further out
+            commands
+                .entity(*entity)
+                .insert((handle, material.0.clone()))
+                .remove::<Aabb>();
+            *lod = Some(target_lod);
+        } else if lod.is_some() {
+            commands.entity(*entity).remove::<Handle<Mesh>>();
+            chunk_meshes.release(*entity, &mut meshes);
+            *lod = None;
+        }
+    }
+}
+
+/// Grow new chunks from their center up to full size, easing out
+pub fn animate_appearing_chunks(
+    mut commands: Commands,
+    time: Res<Time>,
+    animation: Res<ChunkSpawnAnimation>,
+    data_generator: Res<DataGenerator>,
+    mut chunks: Query<(
+        Entity,
+        &Chunk,
+        Option<&Aabb>,
+        &mut Appearing,
+        &mut Transform,
+    )>,
+) {
+    for (entity, chunk, aabb, mut appearing, mut transform) in &mut chunks {
+        appearing.0 += time.delta_seconds();
+        let progress = if animation.0 > 0.0 {
+            (appearing.0 / animation.0).min(1.0)
+        } else {
+            1.0
+        };
+        let scale = 1.0 - (1.0 - progress).powi(3);
+        // The mesh is raised by its elevation above the entity, scale around the mesh instead,
+        // bounds are only computed after the first frame so fall back to the elevation
+        let center = aabb.map_or_else(
+            || {
+                let elevation = data_generator
+                    .get_data_2d(chunk.chunk_pos.x, chunk.chunk_pos.z)
+                    .elevation;
+                Vec3::Y * elevation
+            },
+            |aabb| Vec3::from(aabb.center),
+        );
+        transform.scale = Vec3::splat(scale);
+        transform.translation = chunk.chunk_pos + center * (1.0 - scale);
+        if progress >= 1.0 {
+            commands.entity(entity).remove::<Appearing>();
+        }
+    }
+}
+
+/// Leave chunks deep below the skylights out of directional shadow maps, only with a surface,
+/// underground worlds have no skylight to tell depth from
+pub fn disable_deep_shadows(
+    mut commands: Commands,
+    data_generator: Res<DataGenerator>,
+    chunks: Query<(Entity, &Chunk), Added<Chunk>>,
+) {
+    if data_generator.surface.is_none() {
+        return;
+    }
+    for (entity, chunk) in &chunks {
+        if chunk.chunk_pos.y + CHUNK_SIZE / 2.0 + MAX_ELEVATION < -SKYLIGHT_DEPTH {
+            commands
+                .entity(entity)
+                .insert((NotShadowCaster, NotShadowReceiver));
+        }
+    }
+}
+
+/// Free the meshes of despawned chunks and count the ones still alive
+pub fn release_chunk_meshes(
+    mut removed: RemovedComponents<Chunk>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    mut stats: ResMut<ChunkStats>,
+) {
+    for entity in &mut removed {
+        chunk_meshes.release(entity, &mut meshes);
+    }
+    chunk_meshes
+        .created
+        .retain(|handle| meshes.contains(handle));
+    stats.mesh_assets = chunk_meshes.created.len();
+}
+
+/// Fade to the fog color before the edge of loaded chunks and clip there, so chunks never pop in
+pub fn sync_fog_to_render_distance(
+    render_distance: Res<RenderDistance>,
+    mut cameras: Query<
+        (
+            Ref<StreamingAnchor>,
+            &mut FogSettings,
+            &mut Projection,
+            &mut Camera3d,
+        ),
+        With<Camera>,
+    >,
+) {
+    for (anchor, mut fog, mut projection, mut camera) in &mut cameras {
+        if !render_distance.is_changed() && !anchor.is_added() {
+            continue;
+        }
+        // Chunks are loaded by their center, keep a chunk of margin for their extents
+        let end = render_distance.0 - CHUNK_SIZE;
+        fog.falloff = FogFalloff::Linear {
+            start: end * 0.4,
+            end,
+        };
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.far = render_distance.0;
+        }
+        // Anything past the far plane would have been fully fogged anyway
+        camera.clear_color = ClearColorConfig::Custom(fog.color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::{density, ChunksPlugin};
+
+    #[test]
+    fn crash_dump_round_trip() {
+        let mut app = App::new();
+        app.add_plugins(ChunksPlugin)
+            .register_type::<IVec3>()
+            .register_type::<Vec3>();
+        let registry = app.world.resource::<AppTypeRegistry>().0.clone();
+        let config = WorldGenConfig {
+            seed: 77,
+            density: Some(Arc::new(density::caves())),
+            palette: Palette::Function(Arc::new(|color| color)),
+            surface: Some(default()),
+            reservations: vec![crate::chunks::world_noise::Reservation {
+                min: Vec3::ZERO,
+                max: Vec3::ONE,
+                air: true,
+            }],
+            ..default()
+        };
+        let path = write_crash_dump(&registry, IVec3::new(1, -2, 3), "boom", &config).unwrap();
+        let dump = ChunkCrashDump::load(&registry, &path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(dump.chunk, IVec3::new(1, -2, 3));
+        assert_eq!(dump.error, "boom");
+        assert_eq!(dump.config.seed, 77);
+        assert!(dump.config.surface.is_some());
+        assert_eq!(dump.config.reservations.len(), 1);
+        assert!(dump.custom_density);
+        assert_eq!(dump.decorators, 0);
+        assert_eq!(dump.palette, "Function");
+    }
+}
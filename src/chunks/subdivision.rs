@@ -1,49 +1,122 @@
 use crate::chunks::{
-    render,
+    decoration::ChunkRng,
+    render::{self, ChunkMesh, MeshError, Skirt},
     world_noise::{Data2D, DataGenerator},
-    Chunk, Cube, SMALLEST_CUBE_SIZE,
+    Chunk, Cube, WorldGenConfig,
 };
-use bevy::prelude::*;
+use bevy::{prelude::*, render::primitives::Aabb};
 use rayon::prelude::*;
+use std::time::Duration;
 
 #[allow(clippy::cast_precision_loss)]
-pub fn chunk_render(data_generator: &DataGenerator, chunk_pos: Vec3, chunk_size: f32) -> Chunk {
-    let cubes: Vec<Cube> =
-        subdivide_cube(data_generator, chunk_pos, chunk_size, SMALLEST_CUBE_SIZE);
+pub fn chunk_render(
+    data_generator: &DataGenerator,
+    config: &WorldGenConfig,
+    chunk_pos: Vec3,
+    chunk_size: f32,
+) -> Result<Chunk, MeshError> {
+    let chunk = (chunk_pos / chunk_size).round().as_ivec3();
+    let _span = info_span!("chunk_render", chunk = %chunk).entered();
+    let decorate = |cubes: &mut Vec<Cube>| {
+        for (stream, decorator) in (0..).zip(&config.decorators) {
+            let mut rng = ChunkRng::new(data_generator.seed, chunk, stream);
+            cubes.extend(decorator(data_generator, chunk_pos, &mut rng));
+        }
+    };
+    let subdivide = |cube_size: f32| {
+        let _span = info_span!("subdivide", cube_size).entered();
+        let mut cubes = subdivide_cube(
+            data_generator,
+            chunk_pos,
+            chunk_size,
+            cube_size,
+            config.air_tolerance,
+        );
+        decorate(&mut cubes);
+        cubes
+    };
+    let elevation = data_generator
+        .get_data_2d(chunk_pos.x, chunk_pos.z)
+        .elevation;
+    // Skirts reach as far as the lod's own cubes, the most a neighbour one lod coarser leaves open
+    let skirt = |cube_size: f32| {
+        config.skirts.then_some(Skirt {
+            elevation,
+            half_size: chunk_size / 2.0,
+            depth: cube_size,
+            slack: config.smallest_cube_size / 4.0,
+        })
+    };
+    let build_mesh = |cubes: &Vec<Cube>, cube_size: f32| {
+        let _span = info_span!("mesh", cube_size).entered();
+        render::cubes_mesh(
+            cubes,
+            chunk_pos,
+            &config.palette,
+            config.shading,
+            skirt(cube_size),
+        )
+    };
+
+    let cubes = subdivide(config.smallest_cube_size);
     let mut lods = Vec::new();
     let mut n_triangles = 0;
-    if !cubes.is_empty() {
-        let (mesh, triangles) = render::cubes_mesh(&cubes, chunk_pos);
-        lods.push(mesh);
-        n_triangles += triangles;
-        // Double smallest cube size until reaching chunk_size and add lod
-        let mut cube_size = SMALLEST_CUBE_SIZE;
-        while cube_size < chunk_size {
-            cube_size *= 2.0;
-            let cubes: Vec<Cube> = subdivide_cube(data_generator, chunk_pos, chunk_size, cube_size);
-            if cubes.is_empty() {
-                break;
-            }
-            let (mesh, _triangles) = render::cubes_mesh(&cubes, chunk_pos);
+    let mut compact_indices = false;
+    let mut n_stripped = 0;
+    match build_mesh(&cubes, config.smallest_cube_size) {
+        Ok(ChunkMesh {
+            mesh,
+            n_triangles: triangles,
+            compact_indices: compact,
+            stripped_triangles,
+        }) => {
             lods.push(mesh);
+            n_triangles += triangles;
+            compact_indices = compact;
+            n_stripped = stripped_triangles;
+            // Double smallest cube size until reaching the largest and add lod
+            let mut cube_size = config.smallest_cube_size;
+            while cube_size < config.largest_cube_size {
+                cube_size *= 2.0;
+                match build_mesh(&subdivide(cube_size), cube_size) {
+                    Ok(ChunkMesh { mesh, .. }) => lods.push(mesh),
+                    Err(MeshError::EmptyChunk) => break,
+                    Err(error) => return Err(error),
+                }
+            }
         }
+        Err(MeshError::EmptyChunk) => {}
+        Err(error) => return Err(error),
     }
-    Chunk {
+    Ok(Chunk {
         lods,
+        first_lod: 0,
         chunk_pos,
         n_cubes: cubes.len(),
         n_triangles,
-    }
+        compact_indices,
+        n_stripped,
+        generation_time: Duration::ZERO,
+        cubes,
+    })
 }
 
-#[allow(clippy::cast_precision_loss)]
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
 fn subdivide_cube(
     data_generator: &DataGenerator,
     cube_pos: Vec3,
     cube_size: f32,
     smallest_size: f32,
+    air_tolerance: f32,
 ) -> Vec<Cube> {
     let (px, py, pz) = cube_pos.into();
+    let level = (smallest_size / data_generator.smallest_cube_size)
+        .log2()
+        .round() as usize;
     let mut cubes: Vec<Cube> = Vec::new();
 
     let half_cube_size = cube_size / 2.0;
@@ -52,16 +125,11 @@ fn subdivide_cube(
     // Calculate how much of the cube is air
     let mut n_air_cubes = 0;
     // Smaller cubes have higher threshold for air, so less small cubes made
-    let max_air_cubes: i32 = match cube_size {
-        x if (x - 0.25).abs() < f32::EPSILON => 4,
-        x if (x - 0.5).abs() < f32::EPSILON => 2,
-        x if (x - 1.0).abs() < f32::EPSILON => 1,
-        _ => 0,
-    };
+    let max_air_cubes = (air_tolerance / cube_size).floor() as i32;
 
     for x in [px - half_cube_size, px + half_cube_size] {
         for z in [pz - half_cube_size, pz + half_cube_size] {
-            let data2d = data_generator.get_data_2d(x, z);
+            let data2d = data_generator.get_data_2d_lod(x, z, level);
             for y in [py - half_cube_size, py + half_cube_size] {
                 let is_inside = data_generator.get_data_3d(&data2d, x, z, y);
                 if is_inside {
@@ -70,13 +138,16 @@ fn subdivide_cube(
             }
         }
     }
+    // Reservations can sit between the corners, split down to the lod's size to find them
+    let reserved = half_cube_size >= smallest_size
+        && data_generator.near_reservation(cube_pos, half_cube_size);
     // If fully air, skip
-    if n_air_cubes == 8 {
+    if n_air_cubes == 8 && !reserved {
         return cubes;
     }
     // If air cubes in threshold range, render it
-    if n_air_cubes <= max_air_cubes {
-        let data2d = data_generator.get_data_2d(px, pz);
+    if n_air_cubes <= max_air_cubes && !reserved {
+        let data2d = data_generator.get_data_2d_lod(px, pz, level);
         cubes.push(render_cube(data_generator, &data2d, cube_pos, cube_size));
         return cubes;
     }
@@ -94,7 +165,7 @@ fn subdivide_cube(
 
             let mut local_cubes: Vec<Cube> = Vec::new();
             if half_cube_size < smallest_size {
-                let data2d = data_generator.get_data_2d(c_pos_x, c_pos_z);
+                let data2d = data_generator.get_data_2d_lod(c_pos_x, c_pos_z, level);
                 let is_inside = data_generator.get_data_3d(&data2d, c_pos_x, c_pos_z, c_pos_y);
                 if !is_inside {
                     local_cubes.push(render_cube(
@@ -105,8 +176,13 @@ fn subdivide_cube(
                     ));
                 }
             } else {
-                local_cubes =
-                    subdivide_cube(data_generator, corner_pos, half_cube_size, smallest_size);
+                local_cubes = subdivide_cube(
+                    data_generator,
+                    corner_pos,
+                    half_cube_size,
+                    smallest_size,
+                    air_tolerance,
+                );
             }
             local_cubes.into_par_iter()
         })
@@ -117,10 +193,12 @@ fn subdivide_cube(
 }
 
 fn render_cube(data_generator: &DataGenerator, data2d: &Data2D, pos: Vec3, size: f32) -> Cube {
-    let data_color = data_generator.get_data_color(data2d, pos.x, pos.z, pos.y);
+    let data_color = data_generator.get_data_color(data2d, pos.x, pos.z, pos.y, size);
+    let center = pos + Vec3::Y * data2d.elevation;
     Cube {
         pos: data_color.pos_jittered,
         size: size * 1.175,
         color: data_color.color,
+        bounds: Aabb::from_min_max(center - size / 2.0, center + size / 2.0),
     }
 }
@@ -0,0 +1,121 @@
+use crate::chunks::{
+    render::{self, ChunkMesh, MeshError},
+    streaming::ChunkMaterial,
+    Cube, WorldGenConfig,
+};
+use bevy::{prelude::*, render::primitives::Aabb};
+
+/// A small voxel grid meshed like chunks but on its own entity, so it can move with its transform
+/// (boulders, doors, airships) instead of sitting in the chunk grid
+#[derive(Component, Clone)]
+pub struct VoxelObject {
+    size: UVec3,
+    pub voxel_size: f32,
+    voxels: Vec<Option<Color>>,
+}
+
+impl VoxelObject {
+    /// Empty grid of `size` voxels, centered on the entity
+    pub fn new(size: UVec3, voxel_size: f32) -> Self {
+        VoxelObject {
+            size,
+            voxel_size,
+            voxels: vec![None; (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    /// Fill every voxel where `f` returns a color, given the voxel's offset from the center
+    pub fn from_fn(size: UVec3, voxel_size: f32, f: impl Fn(Vec3) -> Option<Color>) -> Self {
+        let mut object = VoxelObject::new(size, voxel_size);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let pos = UVec3::new(x, y, z);
+                    object.set(pos, f(object.local_position(pos)));
+                }
+            }
+        }
+        object
+    }
+
+    /// Color of the voxel, None if it is air or outside the grid
+    pub fn get(&self, pos: UVec3) -> Option<Color> {
+        self.index(pos).and_then(|index| self.voxels[index])
+    }
+
+    /// Place a voxel or clear it with None, ignored outside the grid, the mesh rebuilds next frame
+    pub fn set(&mut self, pos: UVec3, color: Option<Color>) {
+        if let Some(index) = self.index(pos) {
+            self.voxels[index] = color;
+        }
+    }
+
+    /// Center of a voxel relative to the entity
+    #[allow(clippy::cast_precision_loss)]
+    pub fn local_position(&self, pos: UVec3) -> Vec3 {
+        (pos.as_vec3() + 0.5 - self.size.as_vec3() / 2.0) * self.voxel_size
+    }
+
+    fn index(&self, pos: UVec3) -> Option<usize> {
+        pos.cmplt(self.size)
+            .all()
+            .then(|| (pos.x + self.size.x * (pos.y + self.size.y * pos.z)) as usize)
+    }
+
+    fn cubes(&self) -> Vec<Cube> {
+        let mut cubes = Vec::new();
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let pos = UVec3::new(x, y, z);
+                    let Some(color) = self.get(pos) else {
+                        continue;
+                    };
+                    let center = self.local_position(pos);
+                    cubes.push(Cube {
+                        pos: center,
+                        size: self.voxel_size,
+                        color,
+                        bounds: Aabb::from_min_max(
+                            center - self.voxel_size / 2.0,
+                            center + self.voxel_size / 2.0,
+                        ),
+                    });
+                }
+            }
+        }
+        cubes
+    }
+}
+
+/// Rebuild the mesh of voxel objects that were added or edited, and of all of them when the
+/// palette or shading they were colored with changes
+pub fn mesh_voxel_objects(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<ChunkMaterial>,
+    config: Res<WorldGenConfig>,
+    objects: Query<(Entity, Ref<VoxelObject>)>,
+) {
+    for (entity, object) in &objects {
+        if !object.is_changed() && !config.is_changed() {
+            continue;
+        }
+        let mut entity = commands.entity(entity);
+        match render::cubes_mesh(
+            &object.cubes(),
+            Vec3::ZERO,
+            &config.palette,
+            config.shading,
+            None,
+        ) {
+            Ok(ChunkMesh { mesh, .. }) => {
+                entity.insert((meshes.add(mesh), material.0.clone()));
+            }
+            Err(MeshError::EmptyChunk) => {
+                entity.remove::<Handle<Mesh>>();
+            }
+            Err(error) => warn!("Voxel object failed to mesh: {error}"),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::chunks::{
+    streaming::ChunkMap,
+    world_noise::{DataGenerator, MAX_ELEVATION},
+    Chunk, CHUNK_SIZE,
+};
+use bevy::{ecs::system::SystemParam, prelude::*, render::primitives::Aabb};
+
+#[derive(Reflect)]
+pub struct VoxelSurface {
+    pub size: f32,
+    pub color: Color,
+}
+
+/// Read access to the cubes of loaded chunks, and to the generator for anywhere else
+#[derive(SystemParam)]
+pub struct VoxelWorld<'w, 's> {
+    map: Res<'w, ChunkMap>,
+    data_generator: Res<'w, DataGenerator>,
+    chunks: Query<'w, 's, &'static Chunk>,
+}
+
+impl<'w, 's> VoxelWorld<'w, 's> {
+    /// True if the generator puts rock at world space `pos`, loaded or not
+    pub fn is_solid(&self, pos: Vec3) -> bool {
+        let data2d = self.data_generator.get_data_2d(pos.x, pos.z);
+        // Chunks are drawn raised by their elevation, sample where they were generated
+        let y = pos.y - data2d.elevation;
+        !self.data_generator.get_data_3d(&data2d, pos.x, pos.z, y)
+    }
+
+    /// Loaded chunk holding world space `pos`, None while it is generating or if it is all air
+    pub fn chunk_at(&self, pos: Vec3) -> Option<&Chunk> {
+        let elevation = self.data_generator.get_data_2d(pos.x, pos.z).elevation;
+        let chunk = ((pos - Vec3::Y * elevation) / CHUNK_SIZE)
+            .round()
+            .as_ivec3();
+        self.chunks.get(self.map.chunk_entity(chunk)?).ok()
+    }
+
+    /// Solid cubes of loaded chunks overlapping the box by their exact bounds, positions are cube centers in world space.
+    /// Far chunks that dropped their cubes are sampled from the generator as smallest cubes instead
+    pub fn iter_solid_in_aabb(
+        &self,
+        aabb: Aabb,
+    ) -> impl Iterator<Item = (Vec3, VoxelSurface)> + '_ {
+        let (min, max) = (Vec3::from(aabb.min()), Vec3::from(aabb.max()));
+        // Chunks are generated below where they are drawn, by their elevation
+        let min_chunk = ((min - Vec3::Y * MAX_ELEVATION) / CHUNK_SIZE)
+            .round()
+            .as_ivec3();
+        let max_chunk = (max / CHUNK_SIZE).round().as_ivec3();
+
+        (min_chunk.x..=max_chunk.x)
+            .flat_map(move |x| {
+                (min_chunk.y..=max_chunk.y).flat_map(move |y| {
+                    (min_chunk.z..=max_chunk.z).map(move |z| IVec3::new(x, y, z))
+                })
+            })
+            .filter_map(|pos| Some((pos, self.chunks.get(self.map.chunk_entity(pos)?).ok()?)))
+            .flat_map(
+                move |(pos, chunk)| -> Box<dyn Iterator<Item = (Vec3, VoxelSurface)> + '_> {
+                    // Cubes go along with the finest lods
+                    if chunk.first_lod > 0 {
+                        return Box::new(self.sample_solid(pos, min, max).into_iter());
+                    }
+                    Box::new(
+                        chunk
+                            .cubes
+                            .iter()
+                            .filter(move |cube| {
+                                Vec3::from(cube.bounds.min()).cmple(max).all()
+                                    && Vec3::from(cube.bounds.max()).cmpge(min).all()
+                            })
+                            .map(|cube| {
+                                (
+                                    Vec3::from(cube.bounds.center),
+                                    VoxelSurface {
+                                        size: cube.bounds.half_extents.x * 2.0,
+                                        color: cube.color,
+                                    },
+                                )
+                            }),
+                    )
+                },
+            )
+    }
+
+    /// Smallest cubes the generator puts in `chunk` that overlap `min` to `max`
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn sample_solid(&self, chunk: IVec3, min: Vec3, max: Vec3) -> Vec<(Vec3, VoxelSurface)> {
+        let size = self.data_generator.smallest_cube_size;
+        let cells = (CHUNK_SIZE / size).round() as usize;
+        let half_size = size / 2.0;
+        let corner = chunk.as_vec3() * CHUNK_SIZE - CHUNK_SIZE / 2.0 + half_size;
+        let overlaps = |center: f32, min: f32, max: f32| {
+            center + half_size >= min && center - half_size <= max
+        };
+        let mut solid = Vec::new();
+        for x in 0..cells {
+            for z in 0..cells {
+                let (px, pz) = (corner.x + x as f32 * size, corner.z + z as f32 * size);
+                if !overlaps(px, min.x, max.x) || !overlaps(pz, min.z, max.z) {
+                    continue;
+                }
+                let data2d = self.data_generator.get_data_2d(px, pz);
+                for y in 0..cells {
+                    let py = corner.y + y as f32 * size;
+                    // Chunks are drawn raised by their elevation
+                    let center = Vec3::new(px, py + data2d.elevation, pz);
+                    if !overlaps(center.y, min.y, max.y)
+                        || self.data_generator.get_data_3d(&data2d, px, pz, py)
+                    {
+                        continue;
+                    }
+                    let color = self
+                        .data_generator
+                        .get_data_color(&data2d, px, pz, py, size)
+                        .color;
+                    solid.push((center, VoxelSurface { size, color }));
+                }
+            }
+        }
+        solid
+    }
+}
@@ -1,8 +1,25 @@
+use crate::chunks::{
+    density::{self, DensityFn},
+    noise_backend::WorldNoise,
+    resources::Minerals,
+    WorldGenConfig,
+};
 use bevy::prelude::*;
-use noise::{NoiseFn, OpenSimplex};
-use std::f32::consts::PI;
-
-const ROOM_SPACING: f32 = 150.0;
+use std::{
+    f32::consts::{PI, SQRT_2, TAU},
+    sync::Arc,
+};
+
+pub const ROOM_SPACING: f32 = 150.0;
+/// Chunks are shifted up by up to this much when rendered
+pub const MAX_ELEVATION: f32 = 5.0;
+// Size of the dome carved at spawn, and how far down to look for a natural floor
+const SPAWN_RADIUS: f32 = 6.0;
+const SPAWN_HEIGHT: f32 = 4.0;
+const SPAWN_SEARCH_DEPTH: f32 = 16.0;
+const SPAWN_EYE_HEIGHT: f32 = 1.6;
+/// Depth of grass and soil over the surface rock
+const SURFACE_SOIL_DEPTH: f32 = 1.5;
 
 fn lerp(start: f32, end: f32, percentage: f32) -> f32 {
     start + percentage * (end - start)
@@ -21,8 +38,101 @@ pub enum FloorMaterial {
     Dirt,
 }
 
+/// Rolling hills over the caves, some rooms get a shaft up to them
+#[derive(Reflect, Clone, Copy)]
+pub struct SurfaceSettings {
+    /// Height of the lowest valleys
+    pub level: f32,
+    /// How much higher the hills rise above `level`
+    pub hills: f32,
+}
+
+impl Default for SurfaceSettings {
+    fn default() -> Self {
+        SurfaceSettings {
+            level: 40.0,
+            hills: 20.0,
+        }
+    }
+}
+
+/// A box of world space the generator leaves to game code, filled with air or rock no matter
+/// what the density says, so arenas and hubs can be placed before their chunks generate
+#[derive(Reflect, Clone, Copy)]
+pub struct Reservation {
+    pub min: Vec3,
+    pub max: Vec3,
+    /// True for an empty box to build in, false to fill it with rock
+    pub air: bool,
+}
+
+impl Reservation {
+    fn contains(&self, pos: Vec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmple(self.max).all()
+    }
+
+    fn intersects(&self, min: Vec3, max: Vec3) -> bool {
+        self.min.cmple(max).all() && self.max.cmpge(min).all()
+    }
+}
+
+/// Fold of x and z applied before anything is sampled, so the elevation, colors, surface, spawn and
+/// reservations repeat along with the caves, any difference between the copies is a mesher bug
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Debug)]
+pub enum Symmetry {
+    #[default]
+    None,
+    /// Reflect the positive x half onto the negative
+    MirrorX,
+    /// Repeat one mirrored wedge `segments` times around the y axis
+    Radial { segments: u32 },
+}
+
+impl Symmetry {
+    /// Position in the generated part of the world that `pos` copies, with the transform taking
+    /// offsets there back to world space
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fold(self, pos: Vec2) -> (Vec2, Mat2) {
+        match self {
+            Symmetry::None => (pos, Mat2::IDENTITY),
+            Symmetry::MirrorX if pos.x < 0.0 => (
+                Vec2::new(-pos.x, pos.y),
+                Mat2::from_diagonal(Vec2::new(-1.0, 1.0)),
+            ),
+            Symmetry::MirrorX => (pos, Mat2::IDENTITY),
+            Symmetry::Radial { segments } => {
+                let wedge = TAU / segments.max(1) as f32;
+                let angle = pos.y.atan2(pos.x).rem_euclid(TAU);
+                let base = (angle / wedge).floor() * wedge;
+                let local = angle - base;
+                let rotate = Mat2::from_angle(base);
+                // Every other half wedge is mirrored so the seams line up
+                if local > wedge - local {
+                    let (sin, cos) = wedge.sin_cos();
+                    let reflect = Mat2::from_cols(Vec2::new(cos, sin), Vec2::new(sin, -cos));
+                    let folded = Vec2::from_angle(wedge - local) * pos.length();
+                    (folded, rotate * reflect)
+                } else {
+                    (Vec2::from_angle(local) * pos.length(), rotate)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Resource, Clone)]
 pub struct DataGenerator {
-    pub world_noise: OpenSimplex,
+    pub seed: u32,
+    pub world_noise: Arc<dyn WorldNoise>,
+    /// Floor of the guaranteed air pocket the player starts in
+    pub spawn_floor: Vec3,
+    pub density: Arc<dyn DensityFn>,
+    pub surface: Option<SurfaceSettings>,
+    pub reservations: Arc<[Reservation]>,
+    pub symmetry: Symmetry,
+    /// Cube sizes of the finest and coarsest lods
+    pub smallest_cube_size: f32,
+    pub largest_cube_size: f32,
 }
 
 pub struct Data2D {
@@ -44,20 +154,150 @@ pub struct Data2D {
     pub floor_variance1: f32,
     pub floor_variance2: f32,
     pub floor_variance3: f32,
+    /// Everything above is open sky, infinite without a surface
+    pub surface_height: f32,
+}
+
+impl Data2D {
+    /// Check the values stay in the ranges the density and meshers rely on, NaN from a noise
+    /// setting shows up here before it reaches a mesh
+    pub fn validate(&self) -> Result<(), String> {
+        let channels = [
+            ("smoothness", self.smoothness),
+            ("temperature", self.temperature),
+            ("humidity", self.humidity),
+            ("lushness", self.lushness),
+            ("development", self.development),
+            ("floor variance", self.floor_variance1),
+        ];
+        for (name, value) in channels {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{name} {value} is outside 0 to 1"));
+            }
+        }
+        if !(0.0..=MAX_ELEVATION).contains(&self.elevation) {
+            return Err(format!(
+                "elevation {} is outside 0 to {MAX_ELEVATION}",
+                self.elevation
+            ));
+        }
+        let distances = [
+            ("room size", self.room_size),
+            ("corridor width", self.corridor_width),
+            ("room floor", self.room_floor),
+            ("room ceiling", self.room_ceiling),
+        ];
+        for (name, value) in distances {
+            if !value.is_finite() || value <= 0.0 {
+                return Err(format!("{name} {value} must be finite and positive"));
+            }
+        }
+        if !self.rock_color.is_finite()
+            || !self.room_dist.is_finite()
+            || !self.corridor_dist.is_finite()
+        {
+            return Err(format!(
+                "rock color {}, room distance {} or corridor distance {} is not finite",
+                self.rock_color, self.room_dist, self.corridor_dist
+            ));
+        }
+        // Infinite without a surface, but never NaN
+        if self.surface_height.is_nan() {
+            return Err("surface height is NaN".to_string());
+        }
+        Ok(())
+    }
 }
 
 pub struct DataColor {
-    pub color: Vec3,
+    /// Authored in nonlinear sRGB, convert with `as_linear_rgba_f32` before it reaches a mesh
+    pub color: Color,
     pub pos_jittered: Vec3,
 }
 
 #[allow(clippy::cast_possible_truncation)]
 #[allow(clippy::cast_lossless)]
 impl DataGenerator {
-    pub fn new() -> Self {
-        DataGenerator {
-            world_noise: OpenSimplex::new(4321),
+    pub fn new(config: &WorldGenConfig) -> Self {
+        let mut data_generator = DataGenerator {
+            seed: config.seed,
+            world_noise: config.noise.build(config.seed),
+            spawn_floor: config.spawn,
+            density: config
+                .density
+                .clone()
+                .unwrap_or_else(|| Arc::new(density::caves())),
+            surface: config.surface,
+            reservations: config.reservations.clone().into(),
+            symmetry: config.symmetry,
+            smallest_cube_size: config.smallest_cube_size,
+            largest_cube_size: config.largest_cube_size,
+        };
+        // Without a surface shafts would go up forever
+        if config.surface.is_some() {
+            let density = data_generator.density.clone();
+            data_generator.density = Arc::new(
+                (move |generator: &DataGenerator, data2d: &Data2D, pos: Vec3| {
+                    density.is_air(generator, data2d, pos)
+                })
+                .union(density::Shafts),
+            );
+        }
+        if let Some(warp) = config.warp {
+            let density = data_generator.density.clone();
+            data_generator.density = Arc::new(
+                (move |generator: &DataGenerator, data2d: &Data2D, pos: Vec3| {
+                    density.is_air(generator, data2d, pos)
+                })
+                .warp(warp.scale, warp.strength),
+            );
+        }
+        // Keep the spawn in the generated part of a symmetric world, or its pocket is never carved
+        let (spawn, _) = config
+            .symmetry
+            .fold(Vec2::new(config.spawn.x, config.spawn.z));
+        data_generator.spawn_floor =
+            data_generator.find_spawn_floor(Vec3::new(spawn.x, config.spawn.y, spawn.y));
+        data_generator
+    }
+
+    /// Find the natural ground under the spawn, if the spawn is in rock or over a chasm carve at its height
+    fn find_spawn_floor(&self, spawn: Vec3) -> Vec3 {
+        let data2d = self.get_data_2d(spawn.x, spawn.z);
+        if !self.get_cave_3d(&data2d, spawn.x, spawn.z, spawn.y) {
+            return spawn;
+        }
+        let mut y = spawn.y;
+        while y > spawn.y - SPAWN_SEARCH_DEPTH {
+            y -= self.smallest_cube_size;
+            if !self.get_cave_3d(&data2d, spawn.x, spawn.z, y) {
+                return Vec3::new(spawn.x, y + self.smallest_cube_size, spawn.z);
+            }
         }
+        spawn
+    }
+
+    /// How many lods a chunk has, from the smallest cubes doubling up to the largest
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn n_lods(&self) -> usize {
+        (self.largest_cube_size / self.smallest_cube_size)
+            .log2()
+            .round() as usize
+            + 1
+    }
+
+    /// Highest point of the surface, nothing above it needs streaming
+    pub fn surface_top(&self) -> Option<f32> {
+        self.surface
+            .map(|surface| surface.level + surface.hills + MAX_ELEVATION)
+    }
+
+    /// Eye position inside the spawn pocket, accounting for the elevation offset applied when rendering
+    pub fn safe_spawn_position(&self) -> Vec3 {
+        let elevation = self
+            .get_data_2d(self.spawn_floor.x, self.spawn_floor.z)
+            .elevation;
+        self.spawn_floor + Vec3::Y * (elevation + SPAWN_EYE_HEIGHT)
     }
 
     pub fn get_noise(&self, x: f32) -> f32 {
@@ -79,8 +319,26 @@ impl DataGenerator {
         ((1.0 + (val * 1.4)) * 0.5).clamp(0.0, 1.0) as f32
     }
 
+    /// Minerals in the rock of the column at x and z, they tint the rock color
+    pub fn get_minerals(&self, x: f32, z: f32) -> Minerals {
+        let (Vec2 { x, y: z }, _) = self.symmetry.fold(Vec2::new(x, z));
+        Minerals {
+            calcium: self.get_world_noise2d(6.0, 0.01, x, z),
+            graphite: self.get_world_noise2d(7.0, 0.01, x, z),
+            iron: self.get_world_noise2d(8.0, 0.01, x, z),
+        }
+    }
+
     pub fn get_data_2d(&self, x: f32, z: f32) -> Data2D {
-        let elevation = self.get_world_noise2d(0.0, 0.01, x, z) * 5.0;
+        self.get_data_2d_lod(x, z, 0)
+    }
+
+    /// Column data for generating lod `level`, coarse levels skip material and color noise finer
+    /// than their cubes but keep every channel that shapes the caves so lods still line up
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub fn get_data_2d_lod(&self, x: f32, z: f32, level: usize) -> Data2D {
+        let (Vec2 { x, y: z }, _) = self.symmetry.fold(Vec2::new(x, z));
+        let elevation = self.get_world_noise2d(0.0, 0.01, x, z) * MAX_ELEVATION;
         let smoothness = self.get_world_noise2d(1.0, 0.01, x, z);
 
         let temperature = self.get_world_noise2d(2.0, 0.0025, x, z);
@@ -89,9 +347,11 @@ impl DataGenerator {
         let development = self.get_world_noise2d(5.0, 0.01, x, z);
 
         // Rock types for colour, iron is red, calcium is white, graphite is black, apatite is blue
-        let calcium = self.get_world_noise2d(6.0, 0.01, x, z);
-        let graphite = self.get_world_noise2d(7.0, 0.01, x, z);
-        let iron = self.get_world_noise2d(8.0, 0.01, x, z);
+        let Minerals {
+            calcium,
+            graphite,
+            iron,
+        } = self.get_minerals(x, z);
         let rock_color = Vec3::new(
             calcium * 0.8 - graphite * 0.5 + iron * 0.3,
             calcium * 0.8 - graphite * 0.5 + iron * 0.05,
@@ -154,11 +414,20 @@ impl DataGenerator {
         let room_floor = 8.0 - self.get_world_noise2d(5.0, 0.01, x, z) * 4.0;
         let room_ceiling = 2.0 + self.get_world_noise2d(6.0, 0.01, x, z) * 3.0;
 
-        // Get floor material variables
-        let floor_variance1 = self.get_world_noise2d(7.0, 0.05, x, z);
-        let floor_variance2 = self.get_world_noise2d(8.0, 0.15, x, z) * 0.5;
-        let floor_variance3 = self.get_world_noise2d(9.0, 0.05, x + 500.0, z + 500.0) * 0.5;
-        let noise_offset = self.get_world_noise2d(10.0, 0.05, x, z) * 0.02;
+        // Get floor material variables, ones changing faster than the lod's cubes alias so they
+        // get their typical value there instead
+        let cube_size = f64::from(self.smallest_cube_size) * 2f64.powi(level as i32);
+        let detail = |offset: f64, scale: f64, x: f32, z: f32| {
+            if 1.0 / scale >= cube_size {
+                self.get_world_noise2d(offset, scale, x, z)
+            } else {
+                0.5
+            }
+        };
+        let floor_variance1 = detail(7.0, 0.05, x, z);
+        let floor_variance2 = detail(8.0, 0.15, x, z) * 0.5;
+        let floor_variance3 = detail(9.0, 0.05, x + 500.0, z + 500.0) * 0.5;
+        let noise_offset = detail(10.0, 0.05, x, z) * 0.02;
 
         // Get floor material
         let floor_material = if temperature > 0.6 + noise_offset && humidity < 0.4 + noise_offset {
@@ -177,7 +446,11 @@ impl DataGenerator {
             FloorMaterial::Stone
         };
 
-        Data2D {
+        let surface_height = self.surface.map_or(f32::INFINITY, |surface| {
+            surface.level + self.get_world_noise2d(11.0, 0.005, x, z) * surface.hills
+        });
+
+        let data2d = Data2D {
             elevation,
             smoothness,
             temperature,
@@ -196,44 +469,95 @@ impl DataGenerator {
             floor_variance1,
             floor_variance2,
             floor_variance3,
-        }
+            surface_height,
+        };
+        debug_assert_eq!(data2d.validate(), Ok(()), "bad 2d data at {x} {z}");
+        data2d
     }
 
     pub fn get_data_3d(&self, data2d: &Data2D, x: f32, z: f32, y: f32) -> bool {
-        let room_height_smooth: f32 = if y < 0.0 {
-            data2d.room_floor
-        } else {
-            data2d.room_ceiling
-        };
-        let room_dist_3d: f32 = ((x - data2d.room_position[0]).powi(2)
-            + (z - data2d.room_position[1]).powi(2)
-            + (y * room_height_smooth).powi(2))
-        .sqrt();
-        let room_inside_3d: bool = room_dist_3d < data2d.room_size;
+        let (Vec2 { x, y: z }, _) = self.symmetry.fold(Vec2::new(x, z));
+        self.get_spawn_3d(x, z, y)
+            .or_else(|| self.get_reserved_3d(data2d, x, z, y))
+            .unwrap_or_else(|| self.get_cave_3d(data2d, x, z, y))
+    }
 
-        let corridor_dist_3d: f32 =
-            (data2d.corridor_dist.powi(2) + (y * room_height_smooth / 2.0).powi(2)).sqrt();
-        let corridor_inside_3d: bool = corridor_dist_3d < data2d.corridor_width;
+    /// Whether a reservation could hold part of the cube at `pos`, cubes that might have one
+    /// between their corners have to be split or it is never seen
+    pub fn near_reservation(&self, pos: Vec3, half_size: f32) -> bool {
+        if self.reservations.is_empty() {
+            return false;
+        }
+        // Folding never moves points apart, so the folded cube stays within its diagonal
+        let (folded, _) = self.symmetry.fold(Vec2::new(pos.x, pos.z));
+        let center = Vec3::new(folded.x, pos.y, folded.y);
+        let reach = Vec3::new(half_size * SQRT_2, half_size, half_size * SQRT_2);
+        // The elevation of any column under the cube can lift it
+        let (min, max) = (center - reach, center + reach + Vec3::Y * MAX_ELEVATION);
+        self.reservations
+            .iter()
+            .any(|reservation| reservation.intersects(min, max))
+    }
+
+    /// Fill of the first reservation holding the point, None outside all of them
+    fn get_reserved_3d(&self, data2d: &Data2D, x: f32, z: f32, y: f32) -> Option<bool> {
+        // Reservations are in world space, where chunks end up after the elevation offset
+        let pos = Vec3::new(x, y + data2d.elevation, z);
+        self.reservations
+            .iter()
+            .find(|reservation| reservation.contains(pos))
+            .map(|reservation| reservation.air)
+    }
 
-        room_inside_3d || corridor_inside_3d
+    /// Dome of air over a solid floor at spawn, None outside of it
+    fn get_spawn_3d(&self, x: f32, z: f32, y: f32) -> Option<bool> {
+        let offset = Vec3::new(x, y, z) - self.spawn_floor;
+        let horizontal_dist = Vec2::new(offset.x, offset.z).length();
+        if horizontal_dist > SPAWN_RADIUS {
+            return None;
+        }
+        if (-1.0..0.0).contains(&offset.y) {
+            return Some(false);
+        }
+        let dome_dist =
+            (horizontal_dist / SPAWN_RADIUS).powi(2) + (offset.y / SPAWN_HEIGHT).powi(2);
+        (offset.y >= 0.0 && dome_dist < 1.0).then_some(true)
     }
 
-    pub fn get_data_color(&self, data2d: &Data2D, x: f32, z: f32, y: f32) -> DataColor {
+    fn get_cave_3d(&self, data2d: &Data2D, x: f32, z: f32, y: f32) -> bool {
+        y > data2d.surface_height || self.density.is_air(self, data2d, Vec3::new(x, y, z))
+    }
+
+    /// Color and jittered position of a cube `size` across, bigger cubes get lower frequency noise
+    /// so coarse lods don't alias
+    pub fn get_data_color(&self, data2d: &Data2D, x: f32, z: f32, y: f32, size: f32) -> DataColor {
+        let (folded, to_world) = self.symmetry.fold(Vec2::new(x, z));
         // Color from dark to light gray as elevation increases
         let shade: f32 = y / 50.0;
         let mut color = data2d.rock_color + shade;
 
         // Give the color horizontal lines from noise to make it look more natural
-        let noise_shade: f32 = 0.1 + self.get_noise(y * 20.0 + x * 0.01 + z + 0.01) * 0.1;
+        let detail = (self.smallest_cube_size / size).min(1.0);
+        let noise_shade: f32 =
+            0.1 + self.get_noise((y * 20.0 + folded.y) * detail + folded.x * 0.01 + 0.01) * 0.1;
         color += noise_shade;
         // Add brown colors based on 2d noise
-        let noise_color = 0.5 + self.get_world_noise2d(0.0, 0.1, x, z) / 2.0;
+        let noise_color = 0.5 + self.get_world_noise2d(0.0, 0.1, folded.x, folded.y) / 2.0;
         color += Vec3::new(noise_color * 0.1, noise_color * 0.05, 0.0);
         // Add dark stone patches
         if data2d.floor_variance3 < 0.5 {
             color = color.lerp(color * 0.5, smoothstep(0.5, 0.3, data2d.floor_variance3));
         }
 
+        // Grass on gentle ground at the surface, bare rock where it is rough
+        if y > data2d.surface_height - SURFACE_SOIL_DEPTH {
+            color = if data2d.smoothness > 0.4 {
+                Vec3::new(0.25, 0.45, 0.12).lerp(Vec3::new(0.35, 0.5, 0.15), data2d.lushness)
+            } else {
+                color.lerp(Vec3::splat(0.6), 0.5)
+            };
+        }
+
         // Add color to floors
         // if y < (data2d.room_floor - 4.0) * 4.0 - 2.0 {
         //     let color_variance = data2d.floor_variance1 * 0.15;
@@ -257,16 +581,75 @@ impl DataGenerator {
         //         + Vec3::new(color_variance, color_variance, color_variance);
         // }
 
-        // Jitter the position with noise to make it look more natural
-        let pos_jittered = Vec3::new(
-            x + (self.get_noise2d(z, y) * 0.2),
-            y + data2d.elevation,
-            z + (self.get_noise2d(x, y) * 0.2),
-        );
+        // Jitter the position with noise to make it look more natural, mirrored with the world
+        let jitter = to_world
+            * Vec2::new(
+                self.get_noise2d(folded.y, y) * 0.2,
+                self.get_noise2d(folded.x, y) * 0.2,
+            );
+        let pos_jittered = Vec3::new(x + jitter.x, y + data2d.elevation, z + jitter.y);
 
         DataColor {
-            color,
+            color: Color::rgb(color.x, color.y, color.z),
             pos_jittered,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::noise_backend::NoiseBackend;
+
+    #[test]
+    fn data_2d_stays_in_range() {
+        let backends = [
+            NoiseBackend::OpenSimplex,
+            NoiseBackend::Perlin,
+            NoiseBackend::Value,
+            NoiseBackend::fbm(4),
+        ];
+        for noise in backends {
+            for seed in [0, 1, 4321, u32::MAX] {
+                let data_generator = DataGenerator::new(&WorldGenConfig {
+                    seed,
+                    noise: noise.clone(),
+                    surface: Some(SurfaceSettings::default()),
+                    ..default()
+                });
+                for i in 0..200 {
+                    #[allow(clippy::cast_precision_loss)]
+                    let (x, z) = ((i as f32 * 37.3).sin() * 2000.0, i as f32 * 13.7 - 1000.0);
+                    for level in 0..4 {
+                        let data2d = data_generator.get_data_2d_lod(x, z, level);
+                        assert_eq!(data2d.validate(), Ok(()), "seed {seed} at {x} {z}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn colors_match_across_lods() {
+        let data_generator = DataGenerator::new(&WorldGenConfig::default());
+        for i in 0..500 {
+            #[allow(clippy::cast_precision_loss)]
+            let (x, z, y) = (
+                (i as f32 * 37.3).sin() * 300.0,
+                i as f32 * 1.37 - 300.0,
+                (i % 7) as f32 - 3.0,
+            );
+            let smallest = data_generator.smallest_cube_size;
+            let [fine, coarse] = [(0, smallest), (1, smallest * 2.0)].map(|(level, size)| {
+                let data2d = data_generator.get_data_2d_lod(x, z, level);
+                let color = data_generator.get_data_color(&data2d, x, z, y, size).color;
+                Vec4::from(color.as_rgba_f32())
+            });
+            let difference = (fine - coarse).abs().max_element();
+            assert!(
+                difference < 0.1,
+                "lod colors differ by {difference} at {x} {y} {z}"
+            );
+        }
+    }
+}